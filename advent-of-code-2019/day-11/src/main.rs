@@ -1,72 +1,65 @@
-use intcode::Byte;
+use intcode::{Byte, Intcode, State};
 use std::cmp;
 use std::collections::HashMap;
 
 type Grid = HashMap<(i32, i32), u8>;
 
-struct State {
-    current_position: (i32, i32),
-    current_direction: i32,
-    grid: Grid,
-    output_state: i8,
+struct Robot {
+    position: (i32, i32),
+    direction: i32,
 }
 
-impl State {
+impl Robot {
     fn new() -> Self {
-        State {
-            current_position: (0, 0),
-            current_direction: 0,
-            grid: HashMap::new(),
-            output_state: 0,
+        Robot {
+            position: (0, 0),
+            direction: 0,
         }
     }
-}
-
-impl intcode::Io for State {
-    fn input(&mut self) -> Byte {
-        *self.grid.entry(self.current_position).or_insert(0) as Byte
-    }
 
-    fn output(&mut self, value: Byte) {
-        if self.output_state == 0 {
-            self.grid.insert(self.current_position, value as u8);
-        } else {
-            let dp;
-            let dd;
-            match value {
-                0 => {
-                    // turn left
-                    dp = -1;
-                    dd = 3;
-                }
-                1 => {
-                    // turn right
-                    dp = 1;
-                    dd = 1;
-                }
-                _ => panic!("unhandled program value {:?}", value),
+    fn turn_and_move(&mut self, turn: Byte) {
+        let dp;
+        let dd;
+        match turn {
+            0 => {
+                // turn left
+                dp = -1;
+                dd = 3;
             }
-            let mut x = self.current_position.0;
-            let mut y = self.current_position.1;
-            match self.current_direction {
-                0 => {
-                    x += dp;
-                }
-                1 => {
-                    y += dp;
-                }
-                2 => {
-                    x -= dp;
-                }
-                3 => {
-                    y -= dp;
-                }
-                x => panic!("invalid state (direction): {:?}", x),
-            };
-            self.current_position = (x, y);
-            self.current_direction = (self.current_direction + dd) % 4;
+            1 => {
+                // turn right
+                dp = 1;
+                dd = 1;
+            }
+            _ => panic!("unhandled program value {:?}", turn),
         }
-        self.output_state = (self.output_state + 1) % 2;
+        let mut x = self.position.0;
+        let mut y = self.position.1;
+        match self.direction {
+            0 => {
+                x += dp;
+            }
+            1 => {
+                y += dp;
+            }
+            2 => {
+                x -= dp;
+            }
+            3 => {
+                y -= dp;
+            }
+            x => panic!("invalid state (direction): {:?}", x),
+        };
+        self.position = (x, y);
+        self.direction = (self.direction + dd) % 4;
+    }
+}
+
+fn next_output(machine: &mut Intcode) -> Option<Byte> {
+    match machine.resume().unwrap() {
+        State::Output(value) => Some(value),
+        State::Halted => None,
+        State::NeedsInput => panic!("unexpected input request"),
     }
 }
 
@@ -102,14 +95,29 @@ fn print_grid(grid: &Grid) {
     }
 }
 
-fn run(code: &Vec<Byte>, init: bool) -> Grid {
-    let mut state = State::new();
-
+fn run(code: &[Byte], init: bool) -> Grid {
+    let mut grid = HashMap::new();
+    let mut robot = Robot::new();
     if init {
-        state.grid.insert((0, 0), 1);
+        grid.insert((0, 0), 1);
+    }
+
+    let mut machine = Intcode::new(code.to_vec());
+    loop {
+        match machine.resume().unwrap() {
+            State::NeedsInput => {
+                let color = *grid.entry(robot.position).or_insert(0);
+                machine.push_input(color as Byte);
+            }
+            State::Output(paint) => {
+                grid.insert(robot.position, paint as u8);
+                let turn = next_output(&mut machine).expect("the robot must turn after painting");
+                robot.turn_and_move(turn);
+            }
+            State::Halted => break,
+        }
     }
-    assert!(intcode::run_code(code.to_vec(), &mut state).is_ok());
-    state.grid
+    grid
 }
 
 fn main() {
@@ -121,3 +129,40 @@ fn main() {
     println!("Result for task 2:");
     print_grid(&grid);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use intcode::asm::assemble;
+
+    #[test]
+    fn test_run() {
+        // The robot: paints the panel with the inverted color and turns right, three times,
+        // then halts without reading the color of the last panel.
+        let code = assemble(
+            "
+            in [c]
+            eq [c], #0, [c]
+            out [c]
+            out #1
+            in [c]
+            eq [c], #0, [c]
+            out [c]
+            out #1
+            in [c]
+            eq [c], #0, [c]
+            out [c]
+            out #1
+            hlt
+        c:  db 0
+            ",
+        )
+        .unwrap();
+        let grid = run(&code, false);
+        assert_eq!(3, grid.len());
+        assert!(grid.values().all(|&color| color == 1));
+        let grid = run(&code, true);
+        assert_eq!(3, grid.len());
+        assert_eq!(Some(&0), grid.get(&(0, 0)));
+    }
+}
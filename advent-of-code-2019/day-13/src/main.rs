@@ -1,10 +1,12 @@
-use intcode::Byte;
+use intcode::session::{replay, Recorder, Session};
+use intcode::{Byte, Intcode};
 use std::cmp::Ordering;
 use std::collections::HashMap;
+use std::env;
+use std::process;
 
-fn solve1() {
-    let code = intcode::load_code();
-    let output = intcode::run_code_with_inputs(code, Vec::new());
+fn solve1(code: &[Byte]) {
+    let output = intcode::run_code_with_inputs(code.to_vec(), Vec::new());
 
     let mut count = 0;
     for (i, value) in output.iter().enumerate() {
@@ -104,16 +106,46 @@ impl intcode::Io for Game {
     }
 }
 
-fn play_game() {
-    let mut code = intcode::load_code();
+fn load_free_game(code: &[Byte]) -> Vec<Byte> {
+    let mut code = code.to_vec();
     code[0] = 2; // play for free
+    code
+}
+
+/// Auto plays the game, saving the session to the file if given.
+fn play_game(code: &[Byte], record: Option<&str>) {
     let mut game = Game::new();
-    let res = intcode::run_code(code, &mut game).is_ok();
-    assert!(res);
+    let mut recorder = Recorder::new(&mut game);
+    recorder
+        .run(&mut Intcode::new(load_free_game(code)))
+        .unwrap();
+    if let Some(path) = record {
+        recorder.session().save_to_file(path).unwrap();
+    }
     println!("Result for task 2: {}", game.score);
     game.display();
 }
 
+fn replay_game(code: &[Byte], path: &str) {
+    let session = Session::load_from_file(path).unwrap();
+    match replay(&mut Intcode::new(load_free_game(code)), &session) {
+        Ok(()) => println!("Replayed {} entries", session.entries.len()),
+        Err(err) => {
+            println!("Replay failed: {}", err);
+            process::exit(1);
+        }
+    }
+}
+
 fn main() {
-    play_game();
+    let args: Vec<String> = env::args().collect();
+    let code = intcode::load_code();
+    match (args.get(1).map(String::as_str), args.get(2)) {
+        (Some("--record"), Some(path)) => play_game(&code, Some(path)),
+        (Some("--replay"), Some(path)) => replay_game(&code, path),
+        _ => {
+            solve1(&code);
+            play_game(&code, None);
+        }
+    }
 }
@@ -1,165 +1,69 @@
-use intcode::Byte;
-use std::cmp;
+use intcode::{Byte, Intcode, State};
 use std::collections::{HashMap, HashSet};
-use std::io;
-use std::io::BufRead;
-
-#[derive(Debug)]
-enum Dir {
-    North,
-    South,
-    West,
-    East,
-}
-
-struct Discovery {
-    maze: HashMap<(i32, i32), char>,
-    pos: (i32, i32),
-    dir: Dir,
-    checked: i32,
-    iter: i64,
-}
-
-impl Discovery {
-    fn new() -> Self {
-        let mut maze = HashMap::new();
-        let pos = (0, 0);
-        maze.insert(pos, 'x');
-        Discovery {
-            maze,
-            pos,
-            dir: Dir::East,
-            checked: 0,
-            iter: 0,
-        }
-    }
-
-    fn get_next_pos(&self) -> (i32, i32) {
-        let mut dx = 0;
-        let mut dy = 0;
-        match self.dir {
-            Dir::North => {
-                dy = -1;
-            }
-            Dir::South => {
-                dy = 1;
-            }
-            Dir::West => {
-                dx = -1;
-            }
-            Dir::East => {
-                dx = 1;
-            }
-        };
-        (self.pos.0 + dx, self.pos.1 + dy)
-    }
 
-    fn get_left_turn(&self) -> Dir {
-        match self.dir {
-            Dir::North => Dir::West,
-            Dir::East => Dir::North,
-            Dir::South => Dir::East,
-            Dir::West => Dir::South,
-        }
-    }
+const DIRS: [(Byte, (i32, i32)); 4] = [(1, (0, -1)), (2, (0, 1)), (3, (-1, 0)), (4, (1, 0))];
 
-    fn get_right_turn(&self) -> Dir {
-        match self.dir {
-            Dir::North => Dir::East,
-            Dir::East => Dir::South,
-            Dir::South => Dir::West,
-            Dir::West => Dir::North,
-        }
+// The command moving the droid back.
+fn reverse(command: Byte) -> Byte {
+    match command {
+        1 => 2,
+        2 => 1,
+        3 => 4,
+        _ => 3,
     }
+}
 
-    fn display(&self) {
-        let mut min_x = 0;
-        let mut max_x = 0;
-        let mut min_y = 0;
-        let mut max_y = 0;
-        for ((x, y), _) in self.maze.iter() {
-            min_x = cmp::min(min_x, *x);
-            max_x = cmp::max(max_x, *x);
-            min_y = cmp::min(min_y, *y);
-            max_y = cmp::max(max_y, *y);
-        }
-        let rows_count = max_y - min_y + 1;
-        let cols_count = max_x - min_x + 1;
-
-        let mut data = (0..rows_count)
-            .map(|_| vec!['?'; cols_count as usize])
-            .collect::<Vec<_>>();
-        for (k, v) in self.maze.iter() {
-            let i = (k.1 - min_y) as usize;
-            let j = (k.0 - min_x) as usize;
-            data[i][j] = *v;
-        }
-        for row in data.iter() {
-            row.iter().for_each(|value| print!("{}", value));
-            println!();
-        }
+// Sends the move command to the droid and returns its status.
+fn send(droid: &mut Intcode, command: Byte) -> Byte {
+    droid.push_input(command);
+    match droid.resume() {
+        Ok(State::Output(status)) => status,
+        state => panic!("Unexpected droid state {:?}", state),
     }
 }
 
-impl intcode::Io for Discovery {
-    // This implements a simple maze traversal (right-hand rule)
-    // that may not reveal the whole maze in a general case, but enough for the given problem.
-    fn input(&mut self) -> Byte {
-        match self.dir {
-            Dir::North => 1,
-            Dir::South => 2,
-            Dir::West => 3,
-            Dir::East => 4,
+// Explores the whole maze depth-first, returning the droid to the previous position after
+// every dead end.
+fn explore(droid: &mut Intcode, pos: (i32, i32), cells: &mut HashMap<(i32, i32), char>) {
+    for (command, (dx, dy)) in DIRS.iter() {
+        let next = (pos.0 + dx, pos.1 + dy);
+        if cells.contains_key(&next) {
+            continue;
         }
-    }
-
-    fn output(&mut self, status: Byte) {
-        match status {
+        match send(droid, *command) {
             0 => {
-                // last move was a hit to a wall
-                self.maze.entry(self.get_next_pos()).or_insert('#');
-                self.checked += 1;
-                self.dir = self.get_left_turn();
-            }
-            1 => {
-                let new_pos = self.get_next_pos();
-                self.maze.entry(self.get_next_pos()).or_insert('.');
-                self.dir = self.get_right_turn();
-                self.pos = new_pos;
+                cells.insert(next, '#');
             }
-            2 => {
-                let new_pos = self.get_next_pos();
-                self.maze.entry(self.get_next_pos()).or_insert('O');
-                self.dir = self.get_right_turn();
-                self.pos = new_pos;
-                self.display();
+            status => {
+                cells.insert(next, if status == 2 { 'O' } else { '.' });
+                explore(droid, next, cells);
+                assert_ne!(
+                    0,
+                    send(droid, reverse(*command)),
+                    "The droid can't move back"
+                );
             }
-            _ => panic!("Unhandled status code {}", status),
-        }
-        self.iter += 1;
-        if self.iter % 1000 == 0 {
-            println!("Iteration: {}", self.iter);
-            self.display();
         }
     }
 }
 
-// Run this function for a while on the original input (intcode program) to discover actual maze.
-fn discover_maze() {
-    let code = intcode::load_code();
-    let mut discovery = Discovery::new();
-    let res = intcode::run_code(code, &mut discovery).is_ok();
-    assert!(res);
-}
-
 type Maze = Vec<Vec<char>>;
 
-fn read_maze() -> Maze {
-    io::stdin()
-        .lock()
-        .lines()
-        .map(|line| line.unwrap().trim().chars().collect())
-        .collect()
+// Runs the droid program and builds the maze: the rows are indexed by y, the unreachable cells
+// are `?` and the origin is `x`.
+fn discover_maze(code: Vec<Byte>) -> Maze {
+    let mut cells = HashMap::new();
+    cells.insert((0, 0), 'x');
+    explore(&mut Intcode::new(code), (0, 0), &mut cells);
+    let min_x = cells.keys().map(|pos| pos.0).min().unwrap();
+    let max_x = cells.keys().map(|pos| pos.0).max().unwrap();
+    let min_y = cells.keys().map(|pos| pos.1).min().unwrap();
+    let max_y = cells.keys().map(|pos| pos.1).max().unwrap();
+    let mut maze = vec![vec!['?'; (max_x - min_x + 1) as usize]; (max_y - min_y + 1) as usize];
+    for ((x, y), cell) in cells {
+        maze[(y - min_y) as usize][(x - min_x) as usize] = cell;
+    }
+    maze
 }
 
 fn find_oxygen(maze: &Maze) -> (i32, i32) {
@@ -180,10 +84,7 @@ fn can_visit(maze: &Maze, pos: (i32, i32)) -> bool {
         let i = pos.0 as usize;
         let j = pos.1 as usize;
         if i < maze.len() && j < maze[i].len() {
-            match maze[i][j] {
-                '.' | 'x' => true,
-                _ => false,
-            }
+            matches!(maze[i][j], '.' | 'x')
         } else {
             false
         }
@@ -202,7 +103,7 @@ where
     Predicate: FnMut((i32, i32)) -> bool,
 {
     let mut visited = HashSet::new();
-    let mut front = vec![find_oxygen(&maze)];
+    let mut front = vec![find_oxygen(maze)];
     for pos in front.iter() {
         visited.insert(*pos);
     }
@@ -216,7 +117,7 @@ where
             let i = *i;
             let j = *j;
             for pos in [(i - 1, j), (i + 1, j), (i, j - 1), (i, j + 1)].iter() {
-                if visited.contains(pos) || !can_visit(&maze, *pos) {
+                if visited.contains(pos) || !can_visit(maze, *pos) {
                     continue;
                 }
                 if predicate(*pos) {
@@ -239,9 +140,101 @@ fn count_until_fill(maze: &Maze) -> i32 {
 }
 
 fn main() {
-    let maze = read_maze();
+    let maze = discover_maze(intcode::load_code());
+    display(&maze);
     let result = count_until_origin(&maze) + 1;
     println!("Result for task 1: {:?}", result);
     let result = count_until_fill(&maze);
     println!("Result for task 2: {:?}", result);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use intcode::asm::assemble;
+
+    const MAZE: [&str; 7] = [
+        "#########",
+        "#D....#.#",
+        "#.###.#.#",
+        "#.#...#.#",
+        "#.#.###.#",
+        "#...O...#",
+        "#########",
+    ];
+
+    // The droid program moving in the maze stored in its memory.
+    fn droid() -> Vec<Byte> {
+        let width = MAZE[0].len();
+        let cells: Vec<String> = MAZE
+            .iter()
+            .flat_map(|row| row.chars())
+            .map(|cell| match cell {
+                '#' => "0",
+                'O' => "2",
+                _ => "1",
+            })
+            .map(str::to_string)
+            .collect();
+        let source = format!(
+            "
+            loop:   in [cmd]
+                    add [x], #0, [tx]
+                    add [y], #0, [ty]
+                    eq [cmd], #1, [t]
+                    jf [t], #south
+                    add [ty], #-1, [ty]
+            south:  eq [cmd], #2, [t]
+                    jf [t], #west
+                    add [ty], #1, [ty]
+            west:   eq [cmd], #3, [t]
+                    jf [t], #east
+                    add [tx], #-1, [tx]
+            east:   eq [cmd], #4, [t]
+                    jf [t], #look
+                    add [tx], #1, [tx]
+            look:   mul [ty], #{}, [t]
+                    add [t], [tx], [t]
+                    add [t], #grid, [read+1]
+            read:   add [0], #0, [cell]
+                    jf [cell], #wall
+                    add [tx], #0, [x]
+                    add [ty], #0, [y]
+            wall:   out [cell]
+                    jt #1, #loop
+            cmd:    db 0
+            x:      db 1
+            y:      db 1
+            tx:     db 0
+            ty:     db 0
+            t:      db 0
+            cell:   db 0
+            grid:   db {}
+            ",
+            width,
+            cells.join(", ")
+        );
+        assemble(&source).unwrap()
+    }
+
+    #[test]
+    fn test_discover_maze() {
+        let maze = discover_maze(droid());
+        // The corners are never seen by the droid.
+        let seen: Maze = maze
+            .iter()
+            .map(|row| {
+                row.iter()
+                    .map(|&cell| if cell == '?' { '#' } else { cell })
+                    .collect()
+            })
+            .collect();
+        let expected: Maze = MAZE
+            .iter()
+            .map(|row| row.replace('D', "x").chars().collect())
+            .collect();
+        assert_eq!(expected, seen);
+        assert_eq!(7, count_until_origin(&maze) + 1);
+        assert_eq!(9, count_until_fill(&maze));
+    }
+}
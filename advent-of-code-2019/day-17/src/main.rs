@@ -1,5 +1,11 @@
+use intcode::ascii::run_ascii;
 use intcode::Byte;
-use std::char;
+
+const MAX_LENGTH: usize = 20;
+const NAMES: [&str; 3] = ["A", "B", "C"];
+
+// The directions as (di, dj), clockwise from up.
+const DIRS: [(i32, i32); 4] = [(-1, 0), (0, 1), (1, 0), (0, -1)];
 
 struct Grid {
     data: Vec<Vec<u8>>,
@@ -24,7 +30,7 @@ impl Grid {
             if pos.1 >= self.data[pos.0 as usize].len() as i32 {
                 println!("i, j - out of bounds: {:?}", pos);
             }
-            self.data[pos.0 as usize][pos.1 as usize] != '.' as u8
+            self.data[pos.0 as usize][pos.1 as usize] != b'.'
         } else {
             false
         }
@@ -40,17 +46,139 @@ impl Grid {
         let m = self.data[0].len();
         (n, m)
     }
+
+    fn parse(text: &str) -> Self {
+        Grid::new(
+            text.lines()
+                .map(|row| row.as_bytes().to_vec())
+                .filter(|row| !row.is_empty())
+                .collect(),
+        )
+    }
+
+    // The position of the robot and the index of its direction in `DIRS`.
+    fn find_robot(&self) -> ((i32, i32), usize) {
+        for (i, row) in self.data.iter().enumerate() {
+            for (j, cell) in row.iter().enumerate() {
+                if let Some(dir) = b"^>v<".iter().position(|robot| robot == cell) {
+                    return ((i as i32, j as i32), dir);
+                }
+            }
+        }
+        panic!("The robot is not in view")
+    }
+
+    // Traces the scaffold from the robot to its end, as the commands of the movement
+    // functions: turns (`L` or `R`) followed by the number of steps forward.
+    fn trace_path(&self) -> Vec<String> {
+        let (mut pos, mut dir) = self.find_robot();
+        let step = |pos: (i32, i32), dir: usize| (pos.0 + DIRS[dir].0, pos.1 + DIRS[dir].1);
+        let mut path = Vec::new();
+        loop {
+            if !self.is_scaffold(&step(pos, dir)) {
+                let left = (dir + 3) % 4;
+                let right = (dir + 1) % 4;
+                if self.is_scaffold(&step(pos, left)) {
+                    path.push("L".to_string());
+                    dir = left;
+                } else if self.is_scaffold(&step(pos, right)) {
+                    path.push("R".to_string());
+                    dir = right;
+                } else {
+                    return path;
+                }
+            }
+            let mut count = 0;
+            while self.is_scaffold(&step(pos, dir)) {
+                pos = step(pos, dir);
+                count += 1;
+            }
+            path.push(count.to_string());
+        }
+    }
+}
+
+// The movement functions and the main routine calling them.
+#[derive(Debug, PartialEq)]
+struct Program {
+    main: Vec<usize>,
+    functions: Vec<Vec<String>>,
+}
+
+impl Program {
+    // The input of the robot, without the answer about the video feed.
+    fn to_input(&self) -> String {
+        let main: Vec<&str> = self.main.iter().map(|&f| NAMES[f]).collect();
+        let mut input = main.join(",") + "\n";
+        for function in self.functions.iter() {
+            input += &function.join(",");
+            input += "\n";
+        }
+        input
+    }
+}
+
+fn encoded_len(commands: &[String]) -> usize {
+    commands
+        .iter()
+        .map(|command| command.len() + 1)
+        .sum::<usize>()
+        - 1
+}
+
+// Splits the rest of the path (from `pos`) into calls of at most three functions.
+fn compress_from(path: &[String], pos: usize, program: &mut Program) -> bool {
+    if pos == path.len() {
+        return true;
+    }
+    // The main routine is at most 10 calls long within the limit.
+    if 2 * program.main.len() + 1 > MAX_LENGTH {
+        return false;
+    }
+    for f in 0..program.functions.len() {
+        if path[pos..].starts_with(&program.functions[f]) {
+            program.main.push(f);
+            if compress_from(path, pos + program.functions[f].len(), program) {
+                return true;
+            }
+            program.main.pop();
+        }
+    }
+    if program.functions.len() < NAMES.len() {
+        let mut end = pos + 1;
+        while end <= path.len() && encoded_len(&path[pos..end]) <= MAX_LENGTH {
+            program.functions.push(path[pos..end].to_vec());
+            program.main.push(program.functions.len() - 1);
+            if compress_from(path, end, program) {
+                return true;
+            }
+            program.main.pop();
+            program.functions.pop();
+            end += 1;
+        }
+    }
+    false
+}
+
+fn compress(path: &[String]) -> Option<Program> {
+    let mut program = Program {
+        main: Vec::new(),
+        functions: Vec::new(),
+    };
+    if compress_from(path, 0, &mut program) {
+        Some(program)
+    } else {
+        None
+    }
+}
+
+fn read_grid(code: &[Byte]) -> Grid {
+    let output = run_ascii(code.to_vec(), "").unwrap();
+    Grid::parse(&output.text)
 }
 
 fn solve1(code: &[Byte]) {
-    let output = intcode::run_code_with_inputs(code.to_vec(), Vec::new());
-    let grid = output
-        .split(|v| *v == '\n' as intcode::Byte)
-        .map(|row| row.iter().map(|v| *v as u8).collect::<Vec<_>>())
-        .filter(|row| !row.is_empty())
-        .collect();
-
-    let grid = Grid::new(grid);
+    let grid = read_grid(code);
     let (n, m) = grid.get_size();
     let mut result = 0;
     for i in 0..n {
@@ -64,26 +192,13 @@ fn solve1(code: &[Byte]) {
 }
 
 fn solve2(code: &[Byte]) {
+    let path = read_grid(code).trace_path();
+    let program = compress(&path).expect("The path can't be split into movement functions");
+    print!("{}", program.to_input());
     let mut code = code.to_vec();
     code[0] = 2;
-    let instructions = "\
-A,B,A,B,C,C,B,A,B,C
-L,12,L,10,R,8,L,12
-R,8,R,10,R,12
-L,10,R,12,R,8
-n
-    ";
-    let output = intcode::run_code_with_inputs(
-        code,
-        instructions.chars().rev().map(|ch| ch as Byte).collect(),
-    );
-    for v in output.iter() {
-        if let Some(ch) = char::from_u32(*v as u32) {
-            print!("{}({})", ch, v);
-        } else {
-            println!("{}", v);
-        }
-    }
+    let output = run_ascii(code, &(program.to_input() + "n\n")).unwrap();
+    println!("Result for task 2: {:?}", output.values.last());
 }
 
 fn main() {
@@ -91,3 +206,64 @@ fn main() {
     solve1(&code);
     solve2(&code);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The example of the second part.
+    const SCAFFOLD: &str = "\
+#######...#####
+#.....#...#...#
+#.....#...#...#
+......#...#...#
+......#...###.#
+......#.....#.#
+^########...#.#
+......#.#...#.#
+......#########
+........#...#..
+....#########..
+....#...#......
+....#...#......
+....#...#......
+....#####......
+";
+
+    #[test]
+    fn test_trace_path() {
+        let path = Grid::parse(SCAFFOLD).trace_path();
+        assert_eq!(
+            "R,8,R,8,R,4,R,4,R,8,L,6,L,2,R,4,R,4,R,8,R,8,R,8,L,6,L,2",
+            path.join(",")
+        );
+    }
+
+    fn check_compress(path: &[String]) {
+        let program = compress(path).unwrap();
+        let expanded: Vec<String> = program
+            .main
+            .iter()
+            .flat_map(|&f| program.functions[f].iter().cloned())
+            .collect();
+        assert_eq!(path, &expanded[..]);
+        for line in program.to_input().lines() {
+            assert!(line.len() <= MAX_LENGTH, "{}", line);
+        }
+    }
+
+    #[test]
+    fn test_compress() {
+        check_compress(&Grid::parse(SCAFFOLD).trace_path());
+        // The path of a puzzle input.
+        let (a, b, c) = ("L,12,L,10,R,8,L,12", "R,8,R,10,R,12", "L,10,R,12,R,8");
+        let path = [a, b, a, b, c, c, b, a, b, c].join(",");
+        check_compress(&path.split(',').map(str::to_string).collect::<Vec<_>>());
+
+        // Too long without any repetition.
+        let path: Vec<String> = (10..26)
+            .flat_map(|steps| vec!["R".to_string(), steps.to_string()])
+            .collect();
+        assert_eq!(None, compress(&path));
+    }
+}
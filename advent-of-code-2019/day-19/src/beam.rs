@@ -0,0 +1,217 @@
+// The model of the tractor beam: caches the probed points and tracks the edges of the beam row
+// by row. The beam is a cone from the emitter: in every row it covers a single run of points,
+// and both its edges move right (or stay) from a row to the next, so a row is scanned from the
+// edges of the previous one with a few probes.
+
+use std::collections::HashMap;
+
+// The empty rows (the beam is too narrow to hit the points of the first rows) are scanned up to
+// this many times their number.
+const SCAN_FACTOR: usize = 10;
+const MAX_ROWS: usize = 100_000;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Square {
+    /// The top-left corner.
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl Square {
+    fn contains(&self, x: usize, y: usize) -> bool {
+        self.x <= x && x < self.x + self.width && self.y <= y && y < self.y + self.height
+    }
+}
+
+pub struct Beam<F> {
+    probe: F,
+    probed: HashMap<(usize, usize), bool>,
+    // The first and the last x in the beam for the rows scanned so far, `None` if empty.
+    rows: Vec<Option<(usize, usize)>>,
+}
+
+impl<F: FnMut(usize, usize) -> bool> Beam<F> {
+    /// The beam probed by `probe(x, y)`.
+    pub fn new(probe: F) -> Self {
+        Beam {
+            probe,
+            probed: HashMap::new(),
+            rows: Vec::new(),
+        }
+    }
+
+    /// The number of the points probed so far.
+    pub fn probes(&self) -> usize {
+        self.probed.len()
+    }
+
+    pub fn probe(&mut self, x: usize, y: usize) -> bool {
+        let probe = &mut self.probe;
+        *self.probed.entry((x, y)).or_insert_with(|| probe(x, y))
+    }
+
+    fn scan_row(&mut self) {
+        let y = self.rows.len();
+        let (start, previous_right) = self
+            .rows
+            .iter()
+            .rev()
+            .flatten()
+            .next()
+            .copied()
+            .unwrap_or((0, 0));
+        let edges = match (start..=SCAN_FACTOR * (y + 1)).find(|&x| self.probe(x, y)) {
+            Some(left) => {
+                let mut right = if previous_right > left && self.probe(previous_right, y) {
+                    previous_right
+                } else {
+                    left
+                };
+                while self.probe(right + 1, y) {
+                    right += 1;
+                }
+                Some((left, right))
+            }
+            None => None,
+        };
+        self.rows.push(edges);
+    }
+
+    /// The first and the last x in the beam in the row `y`.
+    pub fn row(&mut self, y: usize) -> Option<(usize, usize)> {
+        while self.rows.len() <= y {
+            self.scan_row();
+        }
+        self.rows[y]
+    }
+
+    pub fn contains(&mut self, x: usize, y: usize) -> bool {
+        matches!(self.row(y), Some((left, right)) if left <= x && x <= right)
+    }
+
+    /// The square of `width` x `height` points fitting in the beam closest to the emitter.
+    pub fn first_fit(&mut self, width: usize, height: usize) -> Option<Square> {
+        assert!(width > 0 && height > 0, "empty square");
+        // The bottom-left corner is on the left edge and the top-right one on the right edge:
+        // the rows between are wide enough since the edges only move right.
+        for bottom in height - 1..MAX_ROWS {
+            let x = match self.row(bottom) {
+                Some((left, _)) => left,
+                None => continue,
+            };
+            let y = bottom + 1 - height;
+            if matches!(self.row(y), Some((left, right)) if left <= x && x + width - 1 <= right) {
+                return Some(Square {
+                    x,
+                    y,
+                    width,
+                    height,
+                });
+            }
+        }
+        None
+    }
+
+    /// Renders the area from the emitter as a PGM image: the beam is white, the square (if
+    /// any) is grey.
+    pub fn render_pgm(&mut self, width: usize, height: usize, square: Option<Square>) -> String {
+        let mut image = format!("P2\n{} {}\n255\n", width, height);
+        for y in 0..height {
+            let row: Vec<&str> = (0..width)
+                .map(|x| {
+                    if square.is_some_and(|square| square.contains(x, y)) {
+                        "128"
+                    } else if self.contains(x, y) {
+                        "255"
+                    } else {
+                        "0"
+                    }
+                })
+                .collect();
+            image.push_str(&row.join(" "));
+            image.push('\n');
+        }
+        image
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The beam from x = 0.7 y to x = 0.9 y: the rows 1 to 3 are empty.
+    fn cone(x: usize, y: usize) -> bool {
+        10 * x >= 7 * y && 10 * x <= 9 * y
+    }
+
+    fn naive_fit(width: usize, height: usize) -> Square {
+        (0..)
+            .flat_map(|y| (0..=y).map(move |x| (x, y)))
+            .find(|&(x, y)| (y..y + height).all(|j| (x..x + width).all(|i| cone(i, j))))
+            .map(|(x, y)| Square {
+                x,
+                y,
+                width,
+                height,
+            })
+            .unwrap()
+    }
+
+    #[test]
+    fn test_rows() {
+        let mut beam = Beam::new(cone);
+        assert_eq!(Some((0, 0)), beam.row(0));
+        assert_eq!(None, beam.row(3));
+        assert_eq!(Some((3, 3)), beam.row(4));
+        assert_eq!(Some((70, 90)), beam.row(100));
+        assert!(beam.contains(80, 100));
+        assert!(!beam.contains(91, 100));
+    }
+
+    #[test]
+    fn test_first_fit() {
+        for &(width, height) in [(1, 1), (2, 3), (3, 2), (5, 5), (10, 4), (4, 10)].iter() {
+            let mut beam = Beam::new(cone);
+            assert_eq!(
+                Some(naive_fit(width, height)),
+                beam.first_fit(width, height)
+            );
+        }
+        let mut beam = Beam::new(cone);
+        let square = beam.first_fit(100, 100).unwrap();
+        assert_eq!(
+            Square {
+                x: 661,
+                y: 845,
+                width: 100,
+                height: 100
+            },
+            square
+        );
+        // A few probes per row instead of the whole area.
+        assert!(beam.probes() < 5 * (square.y + square.height));
+    }
+
+    #[test]
+    fn test_render_pgm() {
+        let mut beam = Beam::new(cone);
+        let square = Some(Square {
+            x: 4,
+            y: 5,
+            width: 1,
+            height: 1,
+        });
+        assert_eq!(
+            "P2\n5 6\n255\n\
+             255 0 0 0 0\n\
+             0 0 0 0 0\n\
+             0 0 0 0 0\n\
+             0 0 0 0 0\n\
+             0 0 0 255 0\n\
+             0 0 0 0 128\n",
+            beam.render_pgm(5, 6, square)
+        );
+    }
+}
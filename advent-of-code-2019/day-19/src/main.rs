@@ -1,93 +1,47 @@
+mod beam;
+
+use beam::Beam;
+use intcode::search::Trial;
 use intcode::Byte;
-use std::cmp::Ordering;
+use std::env;
+use std::fs;
 
-fn check(program: &Vec<Byte>, pos: (i32, i32)) -> bool {
-    let output =
-        intcode::run_code_with_inputs(program.to_vec(), vec![pos.1 as Byte, pos.0 as Byte]);
-    assert_eq!(1, output.len());
-    output[0] == 1
+fn check(program: &[Byte], x: usize, y: usize) -> bool {
+    let outcome = Trial::new()
+        .inputs(&[x as Byte, y as Byte])
+        .run(program)
+        .unwrap();
+    assert_eq!(1, outcome.outputs.len());
+    outcome.outputs[0] == 1
 }
 
-fn solve1() {
-    let program = intcode::load_code();
+fn solve1(program: &[Byte]) {
+    let mut beam = Beam::new(|x, y| check(program, x, y));
     let mut count = 0;
-    for i in 0..50i32 {
-        for j in 0..50i32 {
-            let ch = if check(&program, (i, j)) {
-                count += 1;
-                '#'
-            } else {
-                '.'
-            };
-            print!("{}", ch);
-        }
-        println!();
+    for y in 0..50 {
+        let line: String = (0..50)
+            .map(|x| if beam.contains(x, y) { '#' } else { '.' })
+            .collect();
+        count += line.chars().filter(|&ch| ch == '#').count();
+        println!("{}", line);
     }
     println!("Result for task 1: {}", count);
 }
 
-enum Dir {
-    X,
-    Y,
-}
-
-fn get_range(program: &Vec<Byte>, pos: (i32, i32), dir: Dir) -> i32 {
-    let mut pos = pos;
-    let mut len = 0;
-    loop {
-        if check(program, pos) {
-            len += 1;
-        } else {
-            if len != 0 {
-                break;
-            }
-        }
-        match dir {
-            Dir::X => {
-                pos.0 += 1;
-            }
-            Dir::Y => {
-                pos.1 += 1;
-            }
-        }
-    }
-    len
-}
-
-fn solve2() {
-    let program = intcode::load_code();
-    let mut pos = (0, 0);
-    for i in 0..100 {
-        for j in 0..100 {
-            if check(&program, (i, j)) {
-                pos = (i, j);
-            }
-        }
+// Usage: day-19 [image.pgm], to render the beam with the square.
+fn solve2(program: &[Byte]) {
+    let mut beam = Beam::new(|x, y| check(program, x, y));
+    let square = beam.first_fit(100, 100).unwrap();
+    println!("Probed {} points", beam.probes());
+    println!("Result for task 2: {:?}", square.x * 10000 + square.y);
+    if let Some(path) = env::args().nth(1) {
+        let (width, height) = (square.x + square.width + 10, square.y + square.height + 10);
+        fs::write(path, beam.render_pgm(width, height, Some(square))).unwrap();
     }
-    let min_len = 100;
-    let mut iter = 0;
-    loop {
-        if iter % 100 == 0 {
-            println!("Current {}: {:?}", iter, pos);
-            iter += 1;
-        }
-        let x_len = get_range(&program, pos, Dir::X);
-        let y_len = get_range(&program, pos, Dir::Y);
-        if x_len >= min_len && y_len >= min_len {
-            break;
-        }
-        match x_len.cmp(&y_len) {
-            Ordering::Less => {
-                pos.1 += 1;
-            }
-            _ => {
-                pos.0 += 1;
-            }
-        }
-    }
-    println!("Result for task 2: {:?}", pos.0 * 10000 + pos.1);
 }
 
 fn main() {
-    solve2();
+    let program = intcode::load_code();
+    solve1(&program);
+    solve2(&program);
 }
@@ -1,50 +1,56 @@
-use intcode::Byte;
+use intcode::search::{self, Trial};
+use intcode::{Byte, RuntimeError};
 
-fn reset(code: &mut Vec<Byte>, noun: Byte, verb: Byte) {
+fn reset(code: &mut [Byte], noun: Byte, verb: Byte) {
     code[1] = noun;
     code[2] = verb;
 }
 
-fn solve1() -> Result<Byte, &'static str> {
-    let mut code = intcode::load_code();
+fn solve1(code: &[Byte]) -> Result<Byte, RuntimeError> {
+    let mut code = code.to_vec();
     reset(&mut code, 12, 2);
     let code = intcode::run_code_without_io(code)?;
     Ok(code[0])
 }
 
-fn solve2() -> Result<Byte, &'static str> {
-    let mut code = intcode::load_code();
-    for noun in 0..100 {
-        for verb in 0..100 {
-            reset(&mut code, noun, verb);
-            match intcode::run_code_without_io(code.to_vec()) {
-                Err(_) => (),
-                Ok(res) => {
-                    if res[0] == 19690720 {
-                        return Ok(100 * noun + verb);
-                    }
-                }
-            }
-        }
+fn solve2(code: &[Byte]) -> Result<Byte, &'static str> {
+    let candidates: Vec<(Byte, Byte)> = (0..100)
+        .flat_map(|noun| (0..100).map(move |verb| (noun, verb)))
+        .collect();
+    let found = search::sweep(
+        code,
+        &candidates,
+        |&(noun, verb)| Trial::new().patch(1, noun).patch(2, verb),
+        |outcome| outcome.memory[0] == 19690720,
+    );
+    match found {
+        Some((noun, verb)) => Ok(100 * noun + verb),
+        None => Err("no solution was found"),
     }
-    Err("no solution was found")
 }
 
 fn main() {
-    match solve2() {
+    let code = intcode::load_code();
+    match solve1(&code) {
+        Err(err) => {
+            println!("Error: {:?}", err);
+        }
+        Ok(res) => {
+            println!("Result for task 1: {:?}", res);
+        }
+    }
+    match solve2(&code) {
         Err(err) => {
             println!("Error: {:?}", err);
         }
         Ok(res) => {
-            println!("Result: {:?}", res);
+            println!("Result for task 2: {:?}", res);
         }
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::*;
-
     #[test]
     fn check_run_code() {
         assert_eq!(
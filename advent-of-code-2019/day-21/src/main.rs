@@ -1,62 +1,68 @@
+mod springscript;
+
+use intcode::ascii::{run_ascii, AsciiOutput};
 use intcode::Byte;
-use std::char;
+use springscript::{compile, decode_failure, verify, Hull, Mode};
+
+// The hulls the scripts are checked on before running them.
+const WALK_HULLS: [&str; 3] = [
+    "#####.###########",
+    "#####...#########",
+    "#####..#.########",
+];
+const RUN_HULLS: [&str; 3] = [
+    "#####.#.##..#####",
+    "#####.#.#...#.###",
+    "#####.#.##.#.####",
+];
 
-fn run_program(main_program: &Vec<Byte>, springscript: &str) -> Vec<Byte> {
-    intcode::run_code_with_inputs(
-        main_program.to_vec(),
-        springscript.chars().rev().map(|ch| ch as Byte).collect(),
-    )
+fn run_program(main_program: &[Byte], springscript: &str) -> AsciiOutput {
+    run_ascii(main_program.to_vec(), springscript).unwrap()
 }
 
-fn display_output(output: &Vec<Byte>) {
-    for v in output.iter() {
-        if let Some(ch) = char::from_u32(*v as u32) {
-            print!("{}", ch);
-        } else {
-            print!("{}", v);
-        }
+fn display_output(output: &AsciiOutput) {
+    print!("{}", output.text);
+    for v in output.values.iter() {
+        println!("{}", v);
     }
-    println!();
 }
 
-fn run(script: &str) {
-    let main_program = intcode::load_code();
-    let output = run_program(&main_program, script);
-    display_output(&output);
+fn run(main_program: &[Byte], expression: &str, mode: Mode) {
+    let script = compile(expression, mode).unwrap_or_else(|err| panic!("{}", err));
+    let hulls: Vec<Hull> = WALK_HULLS
+        .iter()
+        .chain(if mode == Mode::Run {
+            &RUN_HULLS[..]
+        } else {
+            &[]
+        })
+        .map(|hull| Hull::parse(hull))
+        .collect();
+    if let Err(failure) = verify(&script, &hulls) {
+        print!("{}\n{}", script, failure);
+        return;
+    }
+    print!("{}", script);
+    let output = run_program(main_program, &script.to_string());
+    match decode_failure(&output.text) {
+        Some(failure) => print!("{}", failure),
+        None => display_output(&output),
+    }
 }
 
-fn solve1() {
-    // The script: jump if there is any hole at A or B or C and D is not a hole:
-    // (not A || not B || not C) && D === not (A and B and C) and D
-    run("\
-NOT T T
-AND A T
-AND B T
-AND C T
-NOT T J
-AND D J
-WALK
-");
+fn solve1(main_program: &[Byte]) {
+    // Jump if there is any hole at A, B or C and D is not a hole.
+    run(main_program, "!(A & B & C) & D", Mode::Walk);
 }
 
-fn solve2() {
-    // The modified script: jump if there is any hole at A, B or C and D is not a hole and
-    // E (next step after D) or H (next jump after D) is not a hole (this way we have an additional
-    // next move)
-    run("\
-OR E J
-OR H J
-AND D J
-NOT T T
-AND A T
-AND B T
-AND C T
-NOT T T
-AND T J
-RUN
-");
+fn solve2(main_program: &[Byte]) {
+    // The same, and E (the next step after D) or H (the next jump after D) is not a hole: this
+    // way there is an additional next move after the landing.
+    run(main_program, "!(A & B & C) & D & (E | H)", Mode::Run);
 }
 
 fn main() {
-    solve2();
+    let main_program = intcode::load_code();
+    solve1(&main_program);
+    solve2(&main_program);
 }
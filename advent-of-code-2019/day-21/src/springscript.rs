@@ -0,0 +1,655 @@
+// The springscript compiler: turns a boolean expression over the sensors (as
+// `!(A & B & C) & D`) into a script for the springdroid, checks the script on simulated hulls
+// and decodes the report of the droid that didn't make it across.
+
+use std::collections::HashMap;
+use std::fmt;
+
+const MAX_INSTRUCTIONS: usize = 15;
+const SENSORS: usize = 9;
+
+/// The ground at the distances 1 to 9 ahead of the droid, from A to I.
+pub type Sensors = [bool; SENSORS];
+
+fn sensor_name(sensor: usize) -> char {
+    (b'A' + sensor as u8) as char
+}
+
+fn all_sensors() -> impl Iterator<Item = Sensors> {
+    (0..1 << SENSORS).map(|bits: usize| {
+        let mut sensors = [false; SENSORS];
+        for (i, sensor) in sensors.iter_mut().enumerate() {
+            *sensor = bits & (1 << i) != 0;
+        }
+        sensors
+    })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Mode {
+    /// Sees the sensors A to D.
+    Walk,
+    /// Sees all the sensors.
+    Run,
+}
+
+impl Mode {
+    fn sensors(self) -> usize {
+        match self {
+            Mode::Walk => 4,
+            Mode::Run => SENSORS,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Expr {
+    Sensor(usize),
+    Not(Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+}
+
+impl Expr {
+    pub fn eval(&self, sensors: &Sensors) -> bool {
+        match self {
+            Expr::Sensor(sensor) => sensors[*sensor],
+            Expr::Not(e) => !e.eval(sensors),
+            Expr::And(a, b) => a.eval(sensors) && b.eval(sensors),
+            Expr::Or(a, b) => a.eval(sensors) || b.eval(sensors),
+        }
+    }
+
+    fn max_sensor(&self) -> usize {
+        match self {
+            Expr::Sensor(sensor) => *sensor,
+            Expr::Not(e) => e.max_sensor(),
+            Expr::And(a, b) | Expr::Or(a, b) => a.max_sensor().max(b.max_sensor()),
+        }
+    }
+}
+
+fn negate(expr: &Expr) -> Expr {
+    match expr {
+        Expr::Not(e) => (**e).clone(),
+        e => Expr::Not(Box::new(e.clone())),
+    }
+}
+
+// The grammar, from the lowest precedence: `a | b`, `a & b`, `!a`, `(a)` and the sensors.
+struct Parser<'a> {
+    chars: std::iter::Peekable<std::str::Chars<'a>>,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&mut self) -> Option<char> {
+        while self.chars.peek().is_some_and(|ch| ch.is_whitespace()) {
+            self.chars.next();
+        }
+        self.chars.peek().copied()
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), CompileError> {
+        match self.peek() {
+            Some(ch) if ch == expected => {
+                self.chars.next();
+                Ok(())
+            }
+            found => Err(CompileError::Syntax(format!(
+                "expected `{}`, found {:?}",
+                expected, found
+            ))),
+        }
+    }
+
+    fn or(&mut self) -> Result<Expr, CompileError> {
+        let mut expr = self.and()?;
+        while self.peek() == Some('|') {
+            self.chars.next();
+            expr = Expr::Or(Box::new(expr), Box::new(self.and()?));
+        }
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> Result<Expr, CompileError> {
+        let mut expr = self.factor()?;
+        while self.peek() == Some('&') {
+            self.chars.next();
+            expr = Expr::And(Box::new(expr), Box::new(self.factor()?));
+        }
+        Ok(expr)
+    }
+
+    fn factor(&mut self) -> Result<Expr, CompileError> {
+        match self.peek() {
+            Some('!') => {
+                self.chars.next();
+                Ok(Expr::Not(Box::new(self.factor()?)))
+            }
+            Some('(') => {
+                self.chars.next();
+                let expr = self.or()?;
+                self.expect(')')?;
+                Ok(expr)
+            }
+            Some(ch @ 'A'..='I') => {
+                self.chars.next();
+                Ok(Expr::Sensor((ch as u8 - b'A') as usize))
+            }
+            found => Err(CompileError::Syntax(format!(
+                "expected a sensor, found {:?}",
+                found
+            ))),
+        }
+    }
+}
+
+pub fn parse_expr(text: &str) -> Result<Expr, CompileError> {
+    let mut parser = Parser {
+        chars: text.chars().peekable(),
+    };
+    let expr = parser.or()?;
+    match parser.peek() {
+        None => Ok(expr),
+        Some(ch) => Err(CompileError::Syntax(format!("unexpected `{}`", ch))),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Register {
+    T,
+    J,
+}
+
+impl Register {
+    fn other(self) -> Register {
+        match self {
+            Register::T => Register::J,
+            Register::J => Register::T,
+        }
+    }
+
+    fn name(self) -> char {
+        match self {
+            Register::T => 'T',
+            Register::J => 'J',
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Source {
+    Sensor(usize),
+    Register(Register),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Op {
+    And,
+    Or,
+    Not,
+}
+
+/// `op x y`: stores the result in `y`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Instruction {
+    pub op: Op,
+    pub x: Source,
+    pub y: Register,
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let op = match self.op {
+            Op::And => "AND",
+            Op::Or => "OR",
+            Op::Not => "NOT",
+        };
+        let x = match self.x {
+            Source::Sensor(sensor) => sensor_name(sensor),
+            Source::Register(register) => register.name(),
+        };
+        write!(f, "{} {} {}", op, x, self.y.name())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Script {
+    pub instructions: Vec<Instruction>,
+    pub mode: Mode,
+}
+
+impl Script {
+    /// Runs the script like the droid does: the registers start false, it jumps if J is true.
+    pub fn eval(&self, sensors: &Sensors) -> bool {
+        let mut registers = [false; 2];
+        for instruction in self.instructions.iter() {
+            let x = match instruction.x {
+                Source::Sensor(sensor) => sensors[sensor],
+                Source::Register(register) => registers[register as usize],
+            };
+            let y = &mut registers[instruction.y as usize];
+            *y = match instruction.op {
+                Op::And => x && *y,
+                Op::Or => x || *y,
+                Op::Not => !x,
+            };
+        }
+        registers[Register::J as usize]
+    }
+}
+
+/// The text sent to the droid.
+impl fmt::Display for Script {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for instruction in self.instructions.iter() {
+            writeln!(f, "{}", instruction)?;
+        }
+        match self.mode {
+            Mode::Walk => writeln!(f, "WALK"),
+            Mode::Run => writeln!(f, "RUN"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum CompileError {
+    Syntax(String),
+    /// The sensor can't be used in the mode.
+    SensorUnavailable(char),
+    /// The expression needs more than the two registers.
+    TooComplex,
+    /// The number of instructions of the shortest script found.
+    TooLong(usize),
+}
+
+impl fmt::Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CompileError::Syntax(message) => write!(f, "syntax error: {}", message),
+            CompileError::SensorUnavailable(sensor) => {
+                write!(f, "the sensor {} is not available in this mode", sensor)
+            }
+            CompileError::TooComplex => write!(f, "the expression needs more registers"),
+            CompileError::TooLong(length) => write!(
+                f,
+                "the script has {} instructions, at most {} are allowed",
+                length, MAX_INSTRUCTIONS
+            ),
+        }
+    }
+}
+
+type Code = Vec<Instruction>;
+
+fn instruction(op: Op, x: Source, y: Register) -> Instruction {
+    Instruction { op, x, y }
+}
+
+// The registers known to be false, indexed by the register.
+type Zeros = [bool; 2];
+
+fn zeros_after(code: &[Instruction], mut zeros: Zeros) -> Zeros {
+    for instruction in code.iter() {
+        zeros[instruction.y as usize] = false;
+    }
+    zeros
+}
+
+fn shortest(candidates: Vec<Option<Code>>) -> Option<Code> {
+    candidates.into_iter().flatten().min_by_key(Vec::len)
+}
+
+// The shortest code found for the expression, the target, the `temp` flag and the zeros.
+type Cache = HashMap<(Expr, Register, bool, Zeros), Option<Code>>;
+
+// Compiles the expression into the `target` register: the other register may be used if
+// `temp` is set. All the ways to do it are tried, the shortest one wins.
+fn compile_into(
+    expr: &Expr,
+    target: Register,
+    temp: bool,
+    zeros: Zeros,
+    cache: &mut Cache,
+) -> Option<Code> {
+    let key = (expr.clone(), target, temp, zeros);
+    if let Some(code) = cache.get(&key) {
+        return code.clone();
+    }
+    let code = compile_uncached(expr, target, temp, zeros, cache);
+    cache.insert(key, code.clone());
+    code
+}
+
+fn compile_uncached(
+    expr: &Expr,
+    target: Register,
+    temp: bool,
+    zeros: Zeros,
+    cache: &mut Cache,
+) -> Option<Code> {
+    let other = target.other();
+    match expr {
+        Expr::Sensor(sensor) => {
+            let sensor = Source::Sensor(*sensor);
+            Some(if zeros[target as usize] {
+                vec![instruction(Op::Or, sensor, target)]
+            } else {
+                vec![
+                    instruction(Op::Not, sensor, target),
+                    instruction(Op::Not, Source::Register(target), target),
+                ]
+            })
+        }
+        Expr::Not(inner) => match &**inner {
+            Expr::Sensor(sensor) => {
+                Some(vec![instruction(Op::Not, Source::Sensor(*sensor), target)])
+            }
+            Expr::Not(e) => compile_into(e, target, temp, zeros, cache),
+            Expr::And(a, b) | Expr::Or(a, b) => {
+                // De Morgan's laws.
+                let pushed = match &**inner {
+                    Expr::And(_, _) => Expr::Or(Box::new(negate(a)), Box::new(negate(b))),
+                    _ => Expr::And(Box::new(negate(a)), Box::new(negate(b))),
+                };
+                shortest(vec![
+                    compile_into(inner, target, temp, zeros, cache).map(|mut code| {
+                        code.push(instruction(Op::Not, Source::Register(target), target));
+                        code
+                    }),
+                    compile_into(&pushed, target, temp, zeros, cache),
+                ])
+            }
+        },
+        Expr::And(a, b) | Expr::Or(a, b) => {
+            let (op, dual) = match expr {
+                Expr::And(_, _) => (Op::And, Op::Or),
+                _ => (Op::Or, Op::And),
+            };
+            let mut candidates = Vec::new();
+            for (x, y) in [(a, b), (b, a)].iter() {
+                match &***y {
+                    Expr::Sensor(sensor) => candidates.push(
+                        compile_into(x, target, temp, zeros, cache).map(|mut code| {
+                            code.push(instruction(op, Source::Sensor(*sensor), target));
+                            code
+                        }),
+                    ),
+                    // x op !s == !(!x dual s), without touching the other register.
+                    Expr::Not(e) if !temp => {
+                        if let Expr::Sensor(sensor) = **e {
+                            candidates.push(compile_into(x, target, temp, zeros, cache).map(
+                                |mut code| {
+                                    let register = Source::Register(target);
+                                    code.push(instruction(Op::Not, register, target));
+                                    code.push(instruction(dual, Source::Sensor(sensor), target));
+                                    code.push(instruction(Op::Not, register, target));
+                                    code
+                                },
+                            ))
+                        }
+                    }
+                    _ => {}
+                }
+                if temp && !matches!(***y, Expr::Sensor(_)) {
+                    // y in the other register first, then x without it.
+                    candidates.push(compile_into(y, other, true, zeros, cache).and_then(
+                        |mut code| {
+                            let rest =
+                                compile_into(x, target, false, zeros_after(&code, zeros), cache)?;
+                            code.extend(rest);
+                            code.push(instruction(op, Source::Register(other), target));
+                            Some(code)
+                        },
+                    ));
+                    // x first, then y in the other register without touching the target.
+                    candidates.push(compile_into(x, target, true, zeros, cache).and_then(
+                        |mut code| {
+                            let rest =
+                                compile_into(y, other, false, zeros_after(&code, zeros), cache)?;
+                            code.extend(rest);
+                            code.push(instruction(op, Source::Register(other), target));
+                            Some(code)
+                        },
+                    ));
+                }
+            }
+            shortest(candidates)
+        }
+    }
+}
+
+/// Compiles the expression telling when to jump into the shortest script found.
+pub fn compile(expression: &str, mode: Mode) -> Result<Script, CompileError> {
+    let expr = parse_expr(expression)?;
+    let max_sensor = expr.max_sensor();
+    if max_sensor >= mode.sensors() {
+        return Err(CompileError::SensorUnavailable(sensor_name(max_sensor)));
+    }
+    let instructions = compile_into(&expr, Register::J, true, [true; 2], &mut HashMap::new())
+        .ok_or(CompileError::TooComplex)?;
+    if instructions.len() > MAX_INSTRUCTIONS {
+        return Err(CompileError::TooLong(instructions.len()));
+    }
+    let script = Script { instructions, mode };
+    debug_assert!(all_sensors().all(|sensors| expr.eval(&sensors) == script.eval(&sensors)));
+    Ok(script)
+}
+
+/// A stretch of the hull: the ground and the holes. Past its end, the hull is ground.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Hull {
+    ground: Vec<bool>,
+}
+
+impl Hull {
+    /// Parses the hull drawn as in the droid's reports: `#####.#..########`.
+    pub fn parse(text: &str) -> Hull {
+        Hull {
+            ground: text.trim().chars().map(|ch| ch != '.').collect(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.ground.len()
+    }
+
+    pub fn is_ground(&self, position: usize) -> bool {
+        self.ground.get(position).copied().unwrap_or(true)
+    }
+
+    /// What the droid at `position` sees.
+    pub fn sensors(&self, position: usize) -> Sensors {
+        let mut sensors = [true; SENSORS];
+        for (i, sensor) in sensors.iter_mut().enumerate() {
+            *sensor = self.is_ground(position + 1 + i);
+        }
+        sensors
+    }
+}
+
+impl fmt::Display for Hull {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for &ground in self.ground.iter() {
+            write!(f, "{}", if ground { '#' } else { '.' })?;
+        }
+        Ok(())
+    }
+}
+
+/// The counterexample of a script: the droid fell in a hole of the hull.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Failure {
+    pub hull: Hull,
+    /// The positions where the droid stood on the ground, in order.
+    pub steps: Vec<usize>,
+    pub hole: usize,
+}
+
+impl Failure {
+    /// The decisions of the droid: the position, what it saw and whether it jumped.
+    pub fn decisions(&self) -> Vec<(usize, Sensors, bool)> {
+        self.steps
+            .iter()
+            .zip(self.steps.iter().skip(1).chain(Some(&self.hole)))
+            .map(|(&from, &to)| (from, self.hull.sensors(from), to - from > 1))
+            .collect()
+    }
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{}", self.hull)?;
+        writeln!(f, "{:>4}  ABCDEFGHI", "")?;
+        for (position, sensors, jumped) in self.decisions() {
+            let seen: String = sensors
+                .iter()
+                .map(|&ground| if ground { '#' } else { '.' })
+                .collect();
+            let action = if jumped { "jump" } else { "walk" };
+            writeln!(f, "{:>4}: {} {}", position, seen, action)?;
+        }
+        writeln!(f, "fell in the hole at {}", self.hole)
+    }
+}
+
+/// Runs the droid over the hull.
+pub fn simulate(script: &Script, hull: &Hull) -> Result<(), Failure> {
+    let mut position = 0;
+    let mut steps = Vec::new();
+    while position < hull.len() {
+        steps.push(position);
+        let next = if script.eval(&hull.sensors(position)) {
+            position + 4
+        } else {
+            position + 1
+        };
+        if !hull.is_ground(next) {
+            return Err(Failure {
+                hull: hull.clone(),
+                steps,
+                hole: next,
+            });
+        }
+        position = next;
+    }
+    Ok(())
+}
+
+/// Checks the script on all the hulls.
+pub fn verify(script: &Script, hulls: &[Hull]) -> Result<(), Failure> {
+    hulls.iter().try_for_each(|hull| simulate(script, hull))
+}
+
+/// Decodes the droid's report of its fall: the frames of 3 lines of air over the hull, with
+/// the droid `@` on the ground line or in the air, and finally in the hole.
+pub fn decode_failure(text: &str) -> Option<Failure> {
+    let start = text.find("Didn't make it across:")?;
+    let lines: Vec<&str> = text[start..]
+        .lines()
+        .skip(1)
+        .filter(|line| !line.is_empty())
+        .collect();
+    let frames: Vec<&[&str]> = lines.chunks_exact(4).collect();
+    let last = frames.last()?;
+    let hole = last[3].find('@')?;
+    Some(Failure {
+        hull: Hull::parse(&last[3].replace('@', ".")),
+        steps: frames
+            .iter()
+            .filter_map(|frame| frame[2].find('@'))
+            .collect(),
+        hole,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check_compile(expression: &str, mode: Mode, length: usize) {
+        let script = compile(expression, mode).unwrap();
+        assert_eq!(length, script.instructions.len(), "{}", script);
+        let expr = parse_expr(expression).unwrap();
+        assert!(all_sensors().all(|sensors| expr.eval(&sensors) == script.eval(&sensors)));
+    }
+
+    #[test]
+    fn test_compile() {
+        check_compile("D", Mode::Walk, 1);
+        check_compile("!A", Mode::Walk, 1);
+        check_compile("!(A & B & C) & D", Mode::Walk, 5);
+        check_compile("!(A & B & C) & D & (E | H)", Mode::Run, 8);
+        check_compile("(A | B) & (C | D) & (E | F)", Mode::Run, 9);
+        check_compile("A & !B", Mode::Walk, 2);
+        check_compile("!(!A | B) | (C & !D)", Mode::Walk, 5);
+        assert_eq!(
+            "NOT A J\nAND D J\nWALK\n",
+            compile("!A & D", Mode::Walk).unwrap().to_string()
+        );
+    }
+
+    #[test]
+    fn test_compile_errors() {
+        assert_eq!(
+            Err(CompileError::SensorUnavailable('E')),
+            compile("A & E", Mode::Walk)
+        );
+        assert!(matches!(
+            compile("A & (B", Mode::Walk),
+            Err(CompileError::Syntax(_))
+        ));
+        assert_eq!(
+            Err(CompileError::TooComplex),
+            compile("(A | B) & (C | D) | (E | F) & (G | H)", Mode::Run)
+        );
+        assert!(matches!(
+            compile(
+                "A & B & C & D & E & F & G & H & I & !A & !B & !C & !D & !E & !F",
+                Mode::Run
+            ),
+            Err(CompileError::TooLong(_))
+        ));
+    }
+
+    #[test]
+    fn test_simulate() {
+        let hull = Hull::parse("#####..#.########");
+        let walk = compile("!(A & B & C) & D", Mode::Walk).unwrap();
+        assert_eq!(Ok(()), simulate(&walk, &hull));
+        // Jumping as soon as possible lands in the second hole.
+        let eager = compile("!A | !B | !C", Mode::Walk).unwrap();
+        let failure = simulate(&eager, &hull).unwrap_err();
+        assert_eq!(vec![0, 1, 2], failure.steps);
+        assert_eq!(6, failure.hole);
+        assert_eq!(
+            vec![false, false, true],
+            failure.decisions().iter().map(|d| d.2).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_decode_failure() {
+        let text = "Input instructions:\n\nWalking...\n\n\nDidn't make it across:\n\n\
+                    .................\n.................\n@................\n#####.###########\n\n\
+                    .................\n.................\n.@...............\n#####.###########\n\n\
+                    .................\n.................\n..@..............\n#####.###########\n\n\
+                    .................\n.................\n...@.............\n#####.###########\n\n\
+                    .................\n.................\n....@............\n#####.###########\n\n\
+                    .................\n.................\n.................\n#####@###########\n\n";
+        let failure = decode_failure(text).unwrap();
+        assert_eq!(Hull::parse("#####.###########"), failure.hull);
+        assert_eq!(vec![0, 1, 2, 3, 4], failure.steps);
+        assert_eq!(5, failure.hole);
+        // The same counterexample as the simulated one.
+        let script = compile("!A & !D", Mode::Walk).unwrap();
+        assert_eq!(
+            Err(failure),
+            simulate(&script, &Hull::parse("#####.###########"))
+        );
+        assert_eq!(
+            None,
+            decode_failure("Input instructions:\n\nWalking...\n\n")
+        );
+    }
+}
@@ -0,0 +1,393 @@
+// The automated player: maps the ship, collects the items that are safe to carry and finds
+// the combination of items that passes the pressure-sensitive floor.
+
+use intcode::{Byte, Intcode, RuntimeError, State};
+use std::collections::{HashMap, HashSet};
+use std::error;
+use std::fmt;
+
+// The limits of the output of one command: the droid is lost past them (the infinite loop).
+const MAX_INSTRUCTIONS: usize = 1_000_000;
+const MAX_OUTPUT: usize = 10_000;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Room {
+    pub name: String,
+    pub doors: Vec<String>,
+    pub items: Vec<String>,
+}
+
+// Parses the list following the line `header`.
+fn parse_list(lines: &[&str], header: &str) -> Vec<String> {
+    match lines.iter().position(|line| *line == header) {
+        Some(start) => lines[start + 1..]
+            .iter()
+            .map_while(|line| line.strip_prefix("- "))
+            .map(str::to_string)
+            .collect(),
+        None => Vec::new(),
+    }
+}
+
+/// Parses the last room described in the text.
+pub fn parse_room(text: &str) -> Option<Room> {
+    let lines: Vec<&str> = text.lines().collect();
+    let start = lines
+        .iter()
+        .rposition(|line| line.starts_with("== ") && line.ends_with(" =="))?;
+    let lines = &lines[start..];
+    Some(Room {
+        name: lines[0][3..lines[0].len() - 3].to_string(),
+        doors: parse_list(lines, "Doors here lead:"),
+        items: parse_list(lines, "Items here:"),
+    })
+}
+
+fn opposite(door: &str) -> Result<&'static str, Error> {
+    match door {
+        "north" => Ok("south"),
+        "south" => Ok("north"),
+        "east" => Ok("west"),
+        "west" => Ok("east"),
+        _ => Err(Error::UnknownDoor(door.to_string())),
+    }
+}
+
+/// The output of the game after a command.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Reply {
+    /// The game waits for the next command.
+    Prompt(String),
+    Halted(String),
+    /// The game didn't ask for a command within the limits.
+    Lost,
+}
+
+/// Why the explorer gave up: the game failed or didn't behave as expected.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Error {
+    Runtime(RuntimeError),
+    Unexpected { command: String, reply: Reply },
+    UnknownDoor(String),
+}
+
+impl From<RuntimeError> for Error {
+    fn from(err: RuntimeError) -> Self {
+        Error::Runtime(err)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Runtime(err) => write!(f, "{}", err),
+            Error::Unexpected { command, reply } => {
+                write!(f, "`{}`: unexpected {:?}", command, reply)
+            }
+            Error::UnknownDoor(door) => write!(f, "unknown door {}", door),
+        }
+    }
+}
+
+impl error::Error for Error {}
+
+pub struct Game {
+    machine: Intcode,
+}
+
+impl Game {
+    pub fn new(code: Vec<Byte>) -> Result<(Self, Reply), RuntimeError> {
+        let mut game = Game {
+            machine: Intcode::new(code),
+        };
+        let reply = game.run()?;
+        Ok((game, reply))
+    }
+
+    fn run(&mut self) -> Result<Reply, RuntimeError> {
+        let mut text = String::new();
+        for _ in 0..MAX_INSTRUCTIONS {
+            match self.machine.step()? {
+                None => {}
+                Some(State::Output(value)) => {
+                    // The values out of the ASCII range are written as numbers.
+                    if (0..128).contains(&value) {
+                        text.push(value as u8 as char);
+                    } else {
+                        text.push_str(&value.to_string());
+                    }
+                    if text.len() > MAX_OUTPUT {
+                        return Ok(Reply::Lost);
+                    }
+                }
+                Some(State::NeedsInput) => return Ok(Reply::Prompt(text)),
+                Some(State::Halted) => return Ok(Reply::Halted(text)),
+            }
+        }
+        Ok(Reply::Lost)
+    }
+
+    pub fn command(&mut self, command: &str) -> Result<Reply, RuntimeError> {
+        for ch in command.chars().chain(Some('\n')) {
+            self.machine.push_input(ch as Byte);
+        }
+        self.run()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Solution {
+    pub password: String,
+    /// The items that pass the floor.
+    pub items: Vec<String>,
+    pub fatal_items: Vec<String>,
+    pub rooms: usize,
+}
+
+struct Explorer {
+    game: Game,
+    // The doors leading from the start to every visited room.
+    paths: HashMap<String, Vec<String>>,
+    carried: Vec<String>,
+    fatal_items: HashSet<String>,
+    // The path to the security checkpoint and the door to the pressure-sensitive floor.
+    checkpoint: Option<(Vec<String>, String)>,
+}
+
+impl Explorer {
+    fn prompt(&mut self, command: &str) -> Result<String, Error> {
+        match self.game.command(command)? {
+            Reply::Prompt(text) => Ok(text),
+            reply => Err(Error::Unexpected {
+                command: command.to_string(),
+                reply,
+            }),
+        }
+    }
+
+    // Takes the item unless it's fatal: tries to take it and to leave the room, then rolls
+    // back to the snapshot of the game taken before the attempt. The items breaking the game
+    // (with a runtime error) are fatal as well.
+    fn take(&mut self, item: &str, room: &Room) -> Result<(), Error> {
+        let checkpoint = self.game.machine.snapshot();
+        let taken = matches!(
+            self.game.command(&format!("take {}", item)),
+            Ok(Reply::Prompt(_))
+        );
+        let fatal = !taken
+            || match room.doors.first().map(|door| self.game.command(door)) {
+                // The droid that can't move stays where it is.
+                Some(Ok(Reply::Prompt(text))) => parse_room(&text).is_none(),
+                Some(_) => true,
+                // There's no way out to try.
+                None => false,
+            };
+        self.game.machine.restore(checkpoint)?;
+        if fatal {
+            self.fatal_items.insert(item.to_string());
+        } else {
+            self.prompt(&format!("take {}", item))?;
+            self.carried.push(item.to_string());
+        }
+        Ok(())
+    }
+
+    fn explore(&mut self, room: Room, path: &mut Vec<String>) -> Result<(), Error> {
+        self.paths.insert(room.name.to_string(), path.to_vec());
+        for item in room.items.iter() {
+            self.take(item, &room)?;
+        }
+        let back = match path.last() {
+            Some(door) => Some(opposite(door)?),
+            None => None,
+        };
+        for door in room.doors.iter() {
+            if Some(door.as_str()) == back {
+                continue;
+            }
+            let return_door = opposite(door)?;
+            let text = self.prompt(door)?;
+            if text.contains("Alert!") {
+                // Ejected back from the floor.
+                self.checkpoint = Some((path.to_vec(), door.to_string()));
+                continue;
+            }
+            let next = match parse_room(&text) {
+                Some(next) => next,
+                None => {
+                    return Err(Error::Unexpected {
+                        command: door.to_string(),
+                        reply: Reply::Prompt(text),
+                    })
+                }
+            };
+            if !self.paths.contains_key(&next.name) {
+                path.push(door.to_string());
+                self.explore(next, path)?;
+                path.pop();
+            }
+            self.prompt(return_door)?;
+        }
+        Ok(())
+    }
+
+    // Tries the subsets of the carried items in the Gray code order: a single item is taken or
+    // dropped between the attempts.
+    fn pass_floor(&mut self) -> Result<Option<Solution>, Error> {
+        let (path, floor) = match self.checkpoint.clone() {
+            Some(checkpoint) => checkpoint,
+            None => return Ok(None),
+        };
+        for door in path.iter() {
+            self.prompt(door)?;
+        }
+        let items = self.carried.to_vec();
+        let mut carried = vec![true; items.len()];
+        for attempt in 0..1usize << items.len() {
+            if attempt > 0 {
+                let i = attempt.trailing_zeros() as usize;
+                carried[i] = !carried[i];
+                let action = if carried[i] { "take" } else { "drop" };
+                self.prompt(&format!("{} {}", action, items[i]))?;
+            }
+            match self.game.command(&floor)? {
+                Reply::Prompt(text) if text.contains("Alert!") => {}
+                Reply::Halted(text) => {
+                    let mut fatal_items: Vec<String> = self.fatal_items.iter().cloned().collect();
+                    fatal_items.sort();
+                    let password = match parse_password(&text) {
+                        Some(password) => password,
+                        None => return Ok(None),
+                    };
+                    return Ok(Some(Solution {
+                        password,
+                        items: (0..items.len())
+                            .filter(|&i| carried[i])
+                            .map(|i| items[i].to_string())
+                            .collect(),
+                        fatal_items,
+                        rooms: self.paths.len(),
+                    }));
+                }
+                reply => {
+                    return Err(Error::Unexpected {
+                        command: floor,
+                        reply,
+                    })
+                }
+            }
+        }
+        Ok(None)
+    }
+}
+
+fn parse_password(text: &str) -> Option<String> {
+    let rest = &text[text.find("typing ")? + "typing ".len()..];
+    let password: String = rest.chars().take_while(char::is_ascii_digit).collect();
+    if password.is_empty() {
+        None
+    } else {
+        Some(password)
+    }
+}
+
+/// Plays the game until the airlock password is printed.
+pub fn solve(code: Vec<Byte>) -> Result<Option<Solution>, Error> {
+    let (game, reply) = Game::new(code)?;
+    let room = match reply {
+        Reply::Prompt(text) => parse_room(&text),
+        _ => None,
+    };
+    let room = match room {
+        Some(room) => room,
+        None => return Ok(None),
+    };
+    let mut explorer = Explorer {
+        game,
+        paths: HashMap::new(),
+        carried: Vec::new(),
+        fatal_items: HashSet::new(),
+        checkpoint: None,
+    };
+    explorer.explore(room, &mut Vec::new())?;
+    explorer.pass_floor()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_room() {
+        let text = "\n\n\n== Hull Breach ==\nYou got in through a hole in the floor here.\n\n\
+                    Doors here lead:\n- east\n- south\n\nItems here:\n- festive hat\n\nCommand?\n";
+        assert_eq!(
+            Some(Room {
+                name: "Hull Breach".to_string(),
+                doors: vec!["east".to_string(), "south".to_string()],
+                items: vec!["festive hat".to_string()],
+            }),
+            parse_room(text)
+        );
+        assert_eq!(
+            None,
+            parse_room("\nYou take the festive hat.\n\nCommand?\n")
+        );
+        assert_eq!(
+            Some("2424308736".to_string()),
+            parse_password("You should be able to get in by typing 2424308736 on the keypad")
+        );
+    }
+
+    #[test]
+    fn test_game() {
+        // The program: output 300 and a new line, read a character and fail.
+        let (mut game, reply) = Game::new(vec![104, 300, 104, 10, 3, 0, 42]).unwrap();
+        assert_eq!(Reply::Prompt("300\n".to_string()), reply);
+        assert_eq!(
+            intcode::ErrorKind::UnknownOpcode(42),
+            game.command("x").unwrap_err().kind
+        );
+    }
+
+    // A game printing the text, then reading a character and halting.
+    fn game(text: &str) -> Vec<Byte> {
+        text.chars()
+            .flat_map(|ch| vec![104, ch as Byte])
+            .chain(vec![3, 0, 99])
+            .collect()
+    }
+
+    #[test]
+    fn test_unexpected_game() {
+        let room = "== Hall ==\n\nDoors here lead:\n- {}\n\nCommand?\n";
+        assert_eq!(
+            Err(Error::UnknownDoor("up".to_string())),
+            solve(game(&room.replace("{}", "up")))
+        );
+        assert_eq!(
+            Err(Error::Unexpected {
+                command: "north".to_string(),
+                reply: Reply::Halted(String::new())
+            }),
+            solve(game(&room.replace("{}", "north")))
+        );
+    }
+
+    #[test]
+    fn test_solve() {
+        let solution = solve(crate::program()).unwrap().unwrap();
+        assert_eq!("196872", solution.password);
+        assert_eq!(
+            vec![
+                "escape pod",
+                "giant electromagnet",
+                "infinite loop",
+                "molten lava",
+                "photons"
+            ],
+            solution.fatal_items
+        );
+        assert_eq!(4, solution.items.len());
+    }
+}
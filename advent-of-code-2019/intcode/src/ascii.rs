@@ -0,0 +1,179 @@
+//! IO of the programs that talk in ASCII text.
+
+use super::{run_code, Byte, Io, RuntimeError};
+use std::collections::VecDeque;
+use std::io;
+use std::io::{BufRead, Cursor, Write};
+
+/// The output of an ASCII program: the text and the values outside of the ASCII range.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct AsciiOutput {
+    pub text: String,
+    pub values: Vec<Byte>,
+}
+
+impl AsciiOutput {
+    fn push(&mut self, value: Byte) {
+        if (0..128).contains(&value) {
+            self.text.push(value as u8 as char);
+        } else {
+            self.values.push(value);
+        }
+    }
+}
+
+/// Feeds the program with the lines of the text input (one character per input request)
+/// and collects its output.
+pub struct AsciiIo<'a> {
+    input: Box<dyn BufRead + 'a>,
+    pending: VecDeque<Byte>,
+    echo: bool,
+    prompt: Option<String>,
+    output: AsciiOutput,
+}
+
+impl<'a> AsciiIo<'a> {
+    pub fn new<R: BufRead + 'a>(input: R) -> Self {
+        AsciiIo {
+            input: Box::new(input),
+            pending: VecDeque::new(),
+            echo: false,
+            prompt: None,
+            output: Default::default(),
+        }
+    }
+
+    pub fn from_text(text: &str) -> Self {
+        AsciiIo::new(Cursor::new(text.as_bytes().to_vec()))
+    }
+
+    pub fn stdin() -> Self {
+        AsciiIo::new(io::BufReader::new(io::stdin()))
+    }
+
+    /// Prints the outputs to the standard output as they come (for interactive programs).
+    pub fn with_echo(mut self) -> Self {
+        self.echo = true;
+        self
+    }
+
+    /// Prints the prompt to the standard output before reading every line of the input.
+    pub fn with_prompt(mut self, prompt: &str) -> Self {
+        self.prompt = Some(prompt.to_string());
+        self
+    }
+
+    pub fn output(&self) -> &AsciiOutput {
+        &self.output
+    }
+
+    pub fn into_output(self) -> AsciiOutput {
+        self.output
+    }
+
+    fn next_input(&mut self) -> Option<Byte> {
+        if self.pending.is_empty() {
+            if let Some(prompt) = self.prompt.as_ref() {
+                print!("{}", prompt);
+                io::stdout().flush().unwrap();
+            }
+            let mut line = String::new();
+            // A read error is treated as the end of the input.
+            self.input.read_line(&mut line).ok()?;
+            self.pending = line.chars().map(|ch| ch as Byte).collect();
+        }
+        self.pending.pop_front()
+    }
+}
+
+impl<'a> Io for AsciiIo<'a> {
+    /// Panics if the input ends: the machines use `try_input`, and fail with
+    /// `ErrorKind::InputExhausted` instead.
+    fn input(&mut self) -> Byte {
+        self.next_input().expect("the input is exhausted")
+    }
+
+    fn try_input(&mut self) -> Option<Byte> {
+        self.next_input()
+    }
+
+    fn output(&mut self, value: Byte) {
+        if self.echo {
+            if (0..128).contains(&value) {
+                print!("{}", value as u8 as char);
+            } else {
+                println!("{}", value);
+            }
+            io::stdout().flush().unwrap();
+        }
+        self.output.push(value);
+    }
+}
+
+/// Runs the program with the text as its input.
+pub fn run_ascii(code: Vec<Byte>, input: &str) -> Result<AsciiOutput, RuntimeError> {
+    let mut io = AsciiIo::from_text(input);
+    run_code(code, &mut io)?;
+    Ok(io.into_output())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::assemble;
+    use crate::ErrorKind;
+
+    // The program: echo the input lines in upper case until an empty line, then output
+    // 200 plus the number of the characters read (out of the ASCII range).
+    fn shout() -> Vec<Byte> {
+        assemble(
+            "
+            loop:   in [ch]
+                    add [count], #1, [count]
+                    eq [ch], #10, [t]
+                    jf [t], #letter
+                    eq [last], #10, [t]
+                    jt [t], #end
+                    jt #1, #print
+            letter: lt [ch], #97, [t]
+                    jt [t], #print
+                    add [ch], #-32, [ch]
+            print:  out [ch]
+                    add [ch], #0, [last]
+                    jt #1, #loop
+            end:    out [count]
+                    hlt
+            ch:     db 0
+            last:   db 0
+            count:  db 200
+            t:      db 0
+            ",
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_run_ascii() {
+        let output = run_ascii(shout(), "hello\nworld!\n\n").unwrap();
+        assert_eq!("HELLO\nWORLD!\n", output.text);
+        assert_eq!(vec![214], output.values);
+
+        let input = "a".repeat(200) + "\n\n";
+        let output = run_ascii(shout(), &input).unwrap();
+        assert_eq!("A".repeat(200) + "\n", output.text);
+        assert_eq!(vec![402], output.values);
+    }
+
+    #[test]
+    fn test_input_exhausted() {
+        let err = run_ascii(shout(), "hello\n").unwrap_err();
+        assert_eq!(ErrorKind::InputExhausted, err.kind);
+    }
+
+    #[test]
+    fn test_io() {
+        let mut io = AsciiIo::new("ab\n\n".as_bytes());
+        crate::run_code(shout(), &mut io).unwrap();
+        assert_eq!("AB\n", io.output().text);
+    }
+}
@@ -0,0 +1,412 @@
+//! Assembler of Intcode programs.
+//!
+//! The syntax follows the listing of the disassembler:
+//!
+//! ```text
+//! ; Echoes the input until it is zero.
+//! loop:   in [value]
+//!         jf [value], #end
+//!         out [value]
+//!         jt #1, #loop
+//! end:    hlt
+//! value:  db 0
+//! ```
+//!
+//! Operands are `[addr]` (position mode), `#imm` (immediate mode) or `rb+off` (relative mode).
+//! An address, value or offset is a number, a label or a label with an offset (`label+2`).
+//! `db` emits its operands as they are: numbers, labels or strings (`"text\n"`).
+//! A numeric label (as `15:` in the disassembler listing) checks the current address.
+
+use super::{Byte, Opcode};
+use std::collections::HashMap;
+use std::error;
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AsmError {
+    /// The line number (1-based) of the failing statement.
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl error::Error for AsmError {}
+
+enum Expr {
+    Number(Byte),
+    Label(String, Byte),
+}
+
+enum Operand {
+    Position(Expr),
+    Immediate(Expr),
+    Relative(Expr),
+}
+
+enum Item {
+    Instruction(Opcode, Vec<Operand>),
+    Data(Vec<Expr>),
+}
+
+impl Item {
+    fn size(&self) -> usize {
+        match self {
+            Item::Instruction(opcode, _) => 1 + opcode.arity(),
+            Item::Data(values) => values.len(),
+        }
+    }
+}
+
+struct Statement {
+    line: usize,
+    item: Item,
+}
+
+type Labels = HashMap<String, usize>;
+
+fn is_identifier(str: &str) -> bool {
+    let mut chars = str.chars();
+    match chars.next() {
+        Some(ch) if ch.is_ascii_alphabetic() || ch == '_' || ch == '.' => {
+            chars.all(|ch| ch.is_ascii_alphanumeric() || ch == '_' || ch == '.')
+        }
+        _ => false,
+    }
+}
+
+fn parse_expr(str: &str) -> Result<Expr, String> {
+    let str = str.trim();
+    if let Ok(value) = str.parse() {
+        return Ok(Expr::Number(value));
+    }
+    let (label, offset) = match str.rfind(['+', '-']) {
+        Some(i) if i > 0 => {
+            let offset = str[i..].trim_start_matches('+').replace(' ', "");
+            let offset = offset
+                .parse()
+                .map_err(|_| format!("invalid offset in `{}`", str))?;
+            (str[..i].trim(), offset)
+        }
+        _ => (str, 0),
+    };
+    if is_identifier(label) {
+        Ok(Expr::Label(label.to_string(), offset))
+    } else {
+        Err(format!("invalid expression `{}`", str))
+    }
+}
+
+fn parse_operand(str: &str) -> Result<Operand, String> {
+    let str = str.trim();
+    if str.starts_with('[') && str.ends_with(']') {
+        Ok(Operand::Position(parse_expr(&str[1..str.len() - 1])?))
+    } else if let Some(value) = str.strip_prefix('#') {
+        Ok(Operand::Immediate(parse_expr(value)?))
+    } else if let Some(offset) = str.strip_prefix("rb") {
+        let offset = offset.trim();
+        if offset.is_empty() {
+            Ok(Operand::Relative(Expr::Number(0)))
+        } else if let Some(offset) = offset.strip_prefix('+') {
+            Ok(Operand::Relative(parse_expr(offset)?))
+        } else if offset.starts_with('-') {
+            Ok(Operand::Relative(parse_expr(offset)?))
+        } else {
+            Err(format!("invalid relative operand `{}`", str))
+        }
+    } else {
+        Err(format!(
+            "operand `{}` must be `[addr]`, `#imm` or `rb+off`",
+            str
+        ))
+    }
+}
+
+fn parse_string(str: &str) -> Result<Vec<Expr>, String> {
+    let mut values = Vec::new();
+    let mut chars = str[1..str.len() - 1].chars();
+    while let Some(ch) = chars.next() {
+        let ch = if ch == '\\' {
+            match chars.next() {
+                Some('n') => '\n',
+                Some('t') => '\t',
+                Some(ch @ '\\') | Some(ch @ '"') => ch,
+                _ => return Err(format!("invalid escape in {}", str)),
+            }
+        } else {
+            ch
+        };
+        values.push(Expr::Number(ch as Byte));
+    }
+    Ok(values)
+}
+
+// Splits the operands by commas, keeping the strings intact.
+fn split_operands(str: &str) -> Result<Vec<&str>, String> {
+    let mut operands = Vec::new();
+    let mut start = 0;
+    let mut in_string = false;
+    let mut escaped = false;
+    for (i, ch) in str.char_indices() {
+        match ch {
+            _ if escaped => escaped = false,
+            '\\' if in_string => escaped = true,
+            '"' => in_string = !in_string,
+            ',' if !in_string => {
+                operands.push(str[start..i].trim());
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    if in_string {
+        return Err("unterminated string".to_string());
+    }
+    let last = str[start..].trim();
+    if !last.is_empty() || !operands.is_empty() {
+        operands.push(last);
+    }
+    Ok(operands)
+}
+
+fn parse_item(str: &str) -> Result<Item, String> {
+    let (mnemonic, rest) = match str.find(char::is_whitespace) {
+        Some(i) => (&str[..i], &str[i..]),
+        None => (str, ""),
+    };
+    let operands = split_operands(rest)?;
+    if operands.iter().any(|operand| operand.is_empty()) {
+        return Err("empty operand".to_string());
+    }
+    if mnemonic == "db" {
+        let mut values = Vec::new();
+        for operand in operands {
+            if operand.starts_with('"') {
+                values.append(&mut parse_string(operand)?);
+            } else {
+                values.push(parse_expr(operand)?);
+            }
+        }
+        return Ok(Item::Data(values));
+    }
+    let opcode = Opcode::from_mnemonic(mnemonic)
+        .ok_or_else(|| format!("unknown mnemonic `{}`", mnemonic))?;
+    if operands.len() != opcode.arity() {
+        return Err(format!(
+            "`{}` expects {} operands, got {}",
+            mnemonic,
+            opcode.arity(),
+            operands.len()
+        ));
+    }
+    let operands = operands
+        .into_iter()
+        .map(parse_operand)
+        .collect::<Result<Vec<_>, _>>()?;
+    if let Some(output) = opcode.output_operand() {
+        if let Operand::Immediate(_) = operands[output - 1] {
+            return Err(format!(
+                "`{}` can't write to an immediate operand",
+                mnemonic
+            ));
+        }
+    }
+    Ok(Item::Instruction(opcode, operands))
+}
+
+fn strip_comment(str: &str) -> &str {
+    let mut in_string = false;
+    let mut escaped = false;
+    for (i, ch) in str.char_indices() {
+        match ch {
+            _ if escaped => escaped = false,
+            '\\' if in_string => escaped = true,
+            '"' => in_string = !in_string,
+            ';' if !in_string => return &str[..i],
+            _ => {}
+        }
+    }
+    str
+}
+
+// Splits a `label:` prefix from the statement (if any).
+fn split_label(str: &str) -> (Option<&str>, &str) {
+    if let Some(i) = str.find(':') {
+        let label = str[..i].trim();
+        if is_identifier(label) || label.parse::<usize>().is_ok() {
+            return (Some(label), str[i + 1..].trim());
+        }
+    }
+    (None, str)
+}
+
+fn parse(source: &str) -> Result<(Vec<Statement>, Labels), AsmError> {
+    let mut statements = Vec::new();
+    let mut labels = HashMap::new();
+    let mut address = 0;
+    for (i, str) in source.lines().enumerate() {
+        let line = i + 1;
+        let error = |message| AsmError { line, message };
+        let (label, str) = split_label(strip_comment(str).trim());
+        if let Some(label) = label {
+            if let Ok(expected) = label.parse::<usize>() {
+                if expected != address {
+                    return Err(error(format!(
+                        "expected address {}, but it is {}",
+                        expected, address
+                    )));
+                }
+            } else if labels.insert(label.to_string(), address).is_some() {
+                return Err(error(format!("duplicate label `{}`", label)));
+            }
+        }
+        if str.is_empty() {
+            continue;
+        }
+        let item = parse_item(str).map_err(error)?;
+        address += item.size();
+        statements.push(Statement { line, item });
+    }
+    Ok((statements, labels))
+}
+
+fn resolve(expr: &Expr, labels: &Labels) -> Result<Byte, String> {
+    match expr {
+        Expr::Number(value) => Ok(*value),
+        Expr::Label(label, offset) => labels
+            .get(label)
+            .map(|address| *address as Byte + offset)
+            .ok_or_else(|| format!("undefined label `{}`", label)),
+    }
+}
+
+fn encode(item: &Item, labels: &Labels, code: &mut Vec<Byte>) -> Result<(), String> {
+    match item {
+        Item::Instruction(opcode, operands) => {
+            let mut instruction = opcode.code();
+            let mut values = Vec::with_capacity(operands.len());
+            let mut factor = 100;
+            for operand in operands {
+                let (mode, expr) = match operand {
+                    Operand::Position(expr) => (0, expr),
+                    Operand::Immediate(expr) => (1, expr),
+                    Operand::Relative(expr) => (2, expr),
+                };
+                instruction += mode * factor;
+                factor *= 10;
+                values.push(resolve(expr, labels)?);
+            }
+            code.push(instruction);
+            code.append(&mut values);
+        }
+        Item::Data(values) => {
+            for value in values {
+                code.push(resolve(value, labels)?);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Assembles the source into a program accepted by `run_code`.
+pub fn assemble(source: &str) -> Result<Vec<Byte>, AsmError> {
+    let (statements, labels) = parse(source)?;
+    let mut code = Vec::new();
+    for statement in statements.iter() {
+        encode(&statement.item, &labels, &mut code).map_err(|message| AsmError {
+            line: statement.line,
+            message,
+        })?;
+    }
+    Ok(code)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::disasm;
+
+    #[test]
+    fn test_assemble() {
+        let source = "
+            ; ask for input, if input is < 8, output 1; else output 0
+                    in [value]
+                    lt [value], #8, [value]   ; store the comparison result in place
+                    out [value]
+                    hlt
+            value:  db -1
+        ";
+        let code = assemble(source).unwrap();
+        assert_eq!(vec![3, 9, 1007, 9, 8, 9, 4, 9, 99, -1], code);
+        assert_eq!(vec![1], crate::run_code_with_inputs(code.to_vec(), vec![7]));
+        assert_eq!(vec![0], crate::run_code_with_inputs(code, vec![8]));
+    }
+
+    #[test]
+    fn test_relative_operands_and_data() {
+        let source = r#"
+                    arb #text
+            loop:   jf rb, #end
+                    out rb+0
+                    arb #1
+                    jt #1, #loop
+            end:    hlt
+            text:   db "Hi\n", 0   ; the string ends with ";" or zero
+        "#;
+        let code = assemble(source).unwrap();
+        assert_eq!(vec![109, 13, 1206, 0, 12, 204, 0], code[..7].to_vec());
+        let output = crate::run_code_with_inputs(code, Vec::new());
+        assert_eq!(vec!['H' as Byte, 'i' as Byte, '\n' as Byte], output);
+    }
+
+    #[test]
+    fn test_label_offsets() {
+        let code = assemble("start: add [start+3], #-1, [end-1]\nend: db 0, start").unwrap();
+        assert_eq!(vec![1001, 3, -1, 3, 0, 0], code);
+    }
+
+    #[test]
+    fn test_disassembled_listing_roundtrip() {
+        let code = vec![
+            109, 1, 204, -1, 1001, 100, 1, 100, 1008, 100, 16, 101, 1006, 101, 0, 99, -7, 42,
+        ];
+        assert_eq!(Ok(code.to_vec()), assemble(&disasm::listing(&code)));
+    }
+
+    #[test]
+    fn test_errors() {
+        let error = |source| assemble(source).unwrap_err();
+        assert_eq!(
+            AsmError {
+                line: 2,
+                message: "unknown mnemonic `nop`".to_string()
+            },
+            error("hlt\nnop")
+        );
+        assert_eq!(
+            "line 1: `add` can't write to an immediate operand",
+            error("add #1, #2, #3").to_string()
+        );
+        assert_eq!(
+            "line 1: `out` expects 1 operands, got 2",
+            error("out #1, #2").to_string()
+        );
+        assert_eq!("line 1: undefined label `x`", error("in [x]").to_string());
+        assert_eq!(
+            "line 2: duplicate label `x`",
+            error("x: hlt\nx: hlt").to_string()
+        );
+        assert_eq!(
+            "line 2: expected address 2, but it is 1",
+            error("hlt\n2: hlt").to_string()
+        );
+        assert_eq!(
+            "line 1: operand `5` must be `[addr]`, `#imm` or `rb+off`",
+            error("out 5").to_string()
+        );
+    }
+}
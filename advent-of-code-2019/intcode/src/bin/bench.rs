@@ -0,0 +1,185 @@
+use intcode::asm::assemble;
+use intcode::{parse_code, Byte, Intcode, State};
+use std::env;
+use std::fs;
+use std::time::{Duration, Instant};
+
+// Benchmarks the interpreter with and without the instruction cache:
+//
+//     cargo run --release --bin bench [-- FILE[:INPUTS] ...]
+//
+// The built-in workloads are run first, followed by the programs in the given files (e.g. the
+// puzzle inputs of the days), each one with its comma-separated inputs. The speedup of the
+// cache is the time without it over the time with it.
+
+// Reads n, outputs the sum of the squares from 1 to n (shared with the transpiler tests).
+const SUM_OF_SQUARES: &str = include_str!("../testdata/sum_of_squares.asm");
+
+// Reads n, outputs the number of primes below n (the sieve of Eratosthenes, with the relative
+// base pointing into the sieve).
+const SIEVE: &str = "
+                in [n]
+                add #2, #0, [i]
+                arb #sieve+2
+        outer:  lt [i], [n], [t]
+                jf [t], #done
+                jt rb+0, #next
+                add [count], #1, [count]
+                add [i], [i], [j]
+                arb [i]
+        inner:  lt [j], [n], [t]
+                jf [t], #back
+                add #1, #0, rb+0
+                arb [i]
+                add [j], [i], [j]
+                jt #1, #inner
+        back:   mul [j], #-1, [t]
+                add [t], [i], [t]
+                arb [t]
+        next:   arb #1
+                add [i], #1, [i]
+                jt #1, #outer
+        done:   out [count]
+                hlt
+        n:      db 0
+        i:      db 0
+        j:      db 0
+        t:      db 0
+        count:  db 0
+        sieve:  db 0
+        ";
+
+// Reads n, outputs the sum of the values from 0 to n - 1 stored in the memory, reading them
+// by patching the address in the code (the style of the day 2 programs).
+const PATCHED_SUM: &str = "
+                in [n]
+        fill:   add [i], #0, [store+3]
+                add #data, [store+3], [store+3]
+        store:  add [i], #0, [0]
+                add [i], #1, [i]
+                lt [i], [n], [t]
+                jt [t], #fill
+                add #data, #0, [load+1]
+        load:   add [0], [sum], [sum]
+                add [load+1], #1, [load+1]
+                add [n], #-1, [n]
+                jt [n], #load
+                out [sum]
+                hlt
+        n:      db 0
+        i:      db 0
+        t:      db 0
+        sum:    db 0
+        data:   db 0
+        ";
+
+// The day 9 quine: a short program, run many times (the cost of creating the machines).
+const QUINE: &str = "109,1,204,-1,1001,100,1,100,1008,100,16,101,1006,101,0,99";
+
+struct Workload {
+    name: String,
+    code: Vec<Byte>,
+    inputs: Vec<Byte>,
+    runs: usize,
+}
+
+struct Measure {
+    executed: u64,
+    output: Vec<Byte>,
+    elapsed: Duration,
+}
+
+fn measure(workload: &Workload, cache: bool) -> Measure {
+    let start = Instant::now();
+    let mut executed = 0;
+    let mut output = Vec::new();
+    for _ in 0..workload.runs {
+        let mut machine = Intcode::new(workload.code.to_vec());
+        machine.set_instruction_cache(cache);
+        for input in workload.inputs.iter() {
+            machine.push_input(*input);
+        }
+        output.clear();
+        loop {
+            match machine.resume() {
+                Ok(State::Output(value)) => output.push(value),
+                Ok(State::Halted) => break,
+                Ok(State::NeedsInput) => panic!("{}: not enough inputs", workload.name),
+                Err(err) => panic!("{}: {}", workload.name, err),
+            }
+        }
+        executed += machine.executed();
+    }
+    Measure {
+        executed,
+        output,
+        elapsed: start.elapsed(),
+    }
+}
+
+fn workload(name: &str, source: &str, inputs: &[Byte], runs: usize) -> Workload {
+    Workload {
+        name: name.to_string(),
+        code: assemble(source).unwrap(),
+        inputs: inputs.to_vec(),
+        runs,
+    }
+}
+
+fn main() {
+    let mut workloads = vec![
+        workload("sum-of-squares", SUM_OF_SQUARES, &[1_000_000], 1),
+        workload("sieve", SIEVE, &[1_000_000], 1),
+        workload("patched-sum", PATCHED_SUM, &[200_000], 1),
+        Workload {
+            name: "quine".to_string(),
+            code: parse_code(QUINE),
+            inputs: Vec::new(),
+            runs: 100_000,
+        },
+    ];
+    for arg in env::args().skip(1) {
+        let (path, inputs) = match arg.rsplit_once(':') {
+            Some((path, inputs)) => (path.to_string(), parse_code(inputs)),
+            None => (arg.to_string(), Vec::new()),
+        };
+        workloads.push(Workload {
+            name: path.to_string(),
+            code: parse_code(&fs::read_to_string(&path).unwrap()),
+            inputs,
+            runs: 1,
+        });
+    }
+
+    println!(
+        "{:<20} {:>5} {:>12} {:>10} {:>8} {:>8}  output",
+        "workload", "cache", "instructions", "time (ms)", "M/s", "speedup"
+    );
+    for workload in workloads.iter() {
+        let mut measures: Vec<Measure> = Vec::new();
+        for &cache in [false, true].iter() {
+            let measure = measure(workload, cache);
+            let seconds = measure.elapsed.as_secs_f64();
+            let speedup = match measures.first() {
+                Some(uncached) => format!("{:.2}", uncached.elapsed.as_secs_f64() / seconds),
+                None => String::new(),
+            };
+            println!(
+                "{:<20} {:>5} {:>12} {:>10.1} {:>8.1} {:>8}  {:?}",
+                workload.name,
+                if cache { "on" } else { "off" },
+                measure.executed,
+                seconds * 1000.0,
+                measure.executed as f64 / seconds / 1e6,
+                speedup,
+                measure.output.last()
+            );
+            measures.push(measure);
+        }
+        assert_eq!(
+            measures[0].output, measures[1].output,
+            "{}: different outputs",
+            workload.name
+        );
+    }
+}
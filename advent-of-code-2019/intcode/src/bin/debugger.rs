@@ -0,0 +1,435 @@
+// An interactive debugger of Intcode programs.
+//
+// Usage: debugger <program-file>
+// The commands are read from the standard input, see `help` for the list.
+use intcode::disasm;
+use intcode::memory::Memory;
+use intcode::snapshot::Snapshot;
+use intcode::{Byte, Intcode, State};
+use std::collections::{BTreeMap, BTreeSet};
+use std::env;
+use std::fs;
+use std::io;
+use std::io::{BufRead, Write};
+
+const HELP: &str = "\
+step [n]            execute n (default 1) instructions
+continue            run until a breakpoint, a watchpoint, an input request or the halt
+next-io             run until the next output or input request
+break <addr>        stop before executing the instruction at the address
+watch <addr>        stop when the value of the memory cell changes
+delete <addr>       remove the breakpoint and the watchpoint at the address
+info                show the breakpoints and the watchpoints
+regs                show the instruction pointer and the relative base
+x <addr> [n]        show n (default 1) memory cells starting at the address
+poke <addr> <value> overwrite the memory cell
+dis [addr] [n]      disassemble n (default 5) instructions (default: at the ip)
+input <values...>   queue numeric inputs
+text <line>         queue the line as ASCII inputs (with a trailing newline)
+save <file>         save the state of the machine to the file
+load <file>         restore the state of the machine from the file
+quit                exit the debugger";
+
+// The reason why the execution was interrupted.
+enum Stop {
+    Breakpoint(usize),
+    Watchpoint(usize, Byte, Byte),
+    State(State),
+    StepsDone,
+}
+
+struct Debugger {
+    machine: Intcode,
+    breakpoints: BTreeSet<usize>,
+    // The watched memory cells with their last seen values.
+    watchpoints: BTreeMap<usize, Byte>,
+    halted: bool,
+}
+
+fn format_value(value: Byte) -> String {
+    match value {
+        32..=126 => format!("{} ('{}')", value, value as u8 as char),
+        10 => format!("{} ('\\n')", value),
+        _ => value.to_string(),
+    }
+}
+
+fn parse_number<T: std::str::FromStr>(arg: Option<&&str>) -> Result<T, String> {
+    match arg {
+        Some(arg) => arg.parse().map_err(|_| format!("invalid number: {}", arg)),
+        None => Err("missing argument".to_string()),
+    }
+}
+
+impl Debugger {
+    fn new(code: Vec<Byte>) -> Self {
+        Debugger {
+            machine: Intcode::new(code),
+            breakpoints: BTreeSet::new(),
+            watchpoints: BTreeMap::new(),
+            halted: false,
+        }
+    }
+
+    fn read_memory(&self, address: usize) -> Byte {
+        self.machine.memory().read(address)
+    }
+
+    fn current_instruction(&self) -> String {
+        let ip = self.machine.ip();
+        match disasm::decode(self.machine.memory().as_slice(), ip) {
+            Some(instruction) => format!("{:>5}: {}", ip, instruction),
+            None => format!("{:>5}: db {}", ip, self.read_memory(ip)),
+        }
+    }
+
+    fn check_watchpoints(&mut self) -> Option<Stop> {
+        let mut changed = None;
+        for (address, value) in self.watchpoints.iter() {
+            let current = self.read_memory(*address);
+            if current != *value && changed.is_none() {
+                changed = Some(Stop::Watchpoint(*address, *value, current));
+            }
+        }
+        let watched: Vec<usize> = self.watchpoints.keys().copied().collect();
+        for address in watched {
+            let current = self.read_memory(address);
+            self.watchpoints.insert(address, current);
+        }
+        changed
+    }
+
+    // Executes instructions until something interesting happens. Outputs are reported in `log`.
+    fn run(
+        &mut self,
+        max_steps: Option<usize>,
+        stop_on_output: bool,
+        log: &mut Vec<String>,
+    ) -> Result<Stop, String> {
+        if self.halted {
+            return Ok(Stop::State(State::Halted));
+        }
+        let mut steps = 0;
+        loop {
+            if max_steps == Some(steps) {
+                return Ok(Stop::StepsDone);
+            }
+            let state = self.machine.step().map_err(|err| err.to_string())?;
+            steps += 1;
+            if let Some(State::Output(value)) = state {
+                log.push(format!("output: {}", format_value(value)));
+            }
+            if state == Some(State::Halted) {
+                self.halted = true;
+            }
+            // The watchpoints are checked whatever the state, so that their values stay current.
+            if let Some(stop) = self.check_watchpoints() {
+                return Ok(stop);
+            }
+            match state {
+                Some(State::Output(value)) if stop_on_output => {
+                    return Ok(Stop::State(State::Output(value)))
+                }
+                Some(State::Output(_)) | None => {}
+                Some(state) => return Ok(Stop::State(state)),
+            }
+            if self.breakpoints.contains(&self.machine.ip()) {
+                return Ok(Stop::Breakpoint(self.machine.ip()));
+            }
+        }
+    }
+
+    fn resume(&mut self, max_steps: Option<usize>, stop_on_output: bool) -> String {
+        let mut log = Vec::new();
+        match self.run(max_steps, stop_on_output, &mut log) {
+            Ok(Stop::Breakpoint(address)) => log.push(format!("breakpoint at {}", address)),
+            Ok(Stop::Watchpoint(address, old, new)) => {
+                log.push(format!("watchpoint at {}: {} -> {}", address, old, new))
+            }
+            Ok(Stop::State(State::NeedsInput)) => log.push("waiting for input".to_string()),
+            Ok(Stop::State(State::Halted)) => log.push("halted".to_string()),
+            Ok(Stop::State(State::Output(_))) | Ok(Stop::StepsDone) => {}
+            Err(err) => log.push(format!("error: {}", err)),
+        }
+        if !self.halted {
+            log.push(self.current_instruction());
+        }
+        log.join("\n")
+    }
+
+    fn disassemble(&self, address: usize, count: usize) -> String {
+        let memory = self.machine.memory().as_slice();
+        let mut address = address;
+        let mut lines = Vec::new();
+        for _ in 0..count {
+            if address >= memory.len() {
+                break;
+            }
+            match disasm::decode(memory, address) {
+                Some(instruction) => {
+                    lines.push(format!("{:>5}: {}", address, instruction));
+                    address += instruction.size();
+                }
+                None => {
+                    lines.push(format!("{:>5}: db {}", address, memory[address]));
+                    address += 1;
+                }
+            }
+        }
+        lines.join("\n")
+    }
+
+    fn execute(&mut self, command: &str) -> Result<String, String> {
+        let args: Vec<&str> = command.split_whitespace().collect();
+        let arg = |i: usize| args.get(i);
+        let output = match args.first().copied().unwrap_or("") {
+            "" => String::new(),
+            "help" | "h" => HELP.to_string(),
+            "step" | "s" => {
+                let steps = if args.len() > 1 {
+                    parse_number(arg(1))?
+                } else {
+                    1
+                };
+                self.resume(Some(steps), false)
+            }
+            "continue" | "c" => self.resume(None, false),
+            "next-io" | "n" => self.resume(None, true),
+            "break" | "b" => {
+                let address = parse_number(arg(1))?;
+                self.breakpoints.insert(address);
+                format!("breakpoint at {}", address)
+            }
+            "watch" | "w" => {
+                let address = parse_number(arg(1))?;
+                self.watchpoints.insert(address, self.read_memory(address));
+                format!("watchpoint at {}", address)
+            }
+            "delete" | "d" => {
+                let address = parse_number(arg(1))?;
+                self.breakpoints.remove(&address);
+                self.watchpoints.remove(&address);
+                format!("deleted {}", address)
+            }
+            "info" | "i" => format!(
+                "breakpoints: {:?}\nwatchpoints: {:?}",
+                self.breakpoints,
+                self.watchpoints.keys().collect::<Vec<_>>()
+            ),
+            "regs" | "r" => format!(
+                "ip: {}\nrelative base: {}",
+                self.machine.ip(),
+                self.machine.relative_base()
+            ),
+            "x" => {
+                let address: usize = parse_number(arg(1))?;
+                let count = if args.len() > 2 {
+                    parse_number(arg(2))?
+                } else {
+                    1
+                };
+                let end = address
+                    .checked_add(count)
+                    .ok_or_else(|| "address out of range".to_string())?;
+                (address..end)
+                    .map(|address| format!("{:>5}: {}", address, self.read_memory(address)))
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            }
+            "poke" | "p" => {
+                let address = parse_number(arg(1))?;
+                let value = parse_number(arg(2))?;
+                self.machine
+                    .write_memory(address, value)
+                    .map_err(|err| err.kind.to_string())?;
+                if let Some(watched) = self.watchpoints.get_mut(&address) {
+                    *watched = value;
+                }
+                format!("{:>5}: {}", address, value)
+            }
+            "dis" => {
+                let address = if args.len() > 1 {
+                    parse_number(arg(1))?
+                } else {
+                    self.machine.ip()
+                };
+                let count = if args.len() > 2 {
+                    parse_number(arg(2))?
+                } else {
+                    5
+                };
+                self.disassemble(address, count)
+            }
+            "input" => {
+                for value in args[1..].iter() {
+                    self.machine.push_input(parse_number(Some(value))?);
+                }
+                format!("queued {} inputs", args.len() - 1)
+            }
+            "text" => {
+                let line = command.trim_start()[4..].trim_start();
+                line.chars()
+                    .chain("\n".chars())
+                    .for_each(|ch| self.machine.push_input(ch as Byte));
+                format!("queued {} inputs", line.len() + 1)
+            }
+            "save" => {
+                let path = arg(1).ok_or("missing argument")?;
+                let snapshot = self.machine.snapshot();
+                snapshot.save_to_file(path).map_err(|err| err.to_string())?;
+                format!("saved to {}", path)
+            }
+            "load" => {
+                let path = arg(1).ok_or("missing argument")?;
+                let snapshot = Snapshot::load_from_file(path).map_err(|err| err.to_string())?;
+                self.machine
+                    .restore(snapshot)
+                    .map_err(|err| err.kind.to_string())?;
+                self.halted = false;
+                for (address, value) in self.watchpoints.iter_mut() {
+                    *value = self.machine.memory().read(*address);
+                }
+                self.current_instruction()
+            }
+            command => return Err(format!("unknown command: {} (try `help`)", command)),
+        };
+        Ok(output)
+    }
+}
+
+fn main() {
+    let path = match env::args().nth(1) {
+        Some(path) => path,
+        None => {
+            eprintln!("Usage: debugger <program-file>");
+            return;
+        }
+    };
+    let code = intcode::parse_code(&fs::read_to_string(path).unwrap());
+    let mut debugger = Debugger::new(code);
+    println!("{}", debugger.current_instruction());
+    let stdin = io::stdin();
+    loop {
+        print!("(intcode) ");
+        io::stdout().flush().unwrap();
+        let mut line = String::new();
+        if stdin.lock().read_line(&mut line).unwrap() == 0 {
+            break;
+        }
+        match line.trim() {
+            "quit" | "q" => break,
+            command => match debugger.execute(command) {
+                Ok(output) if output.is_empty() => {}
+                Ok(output) => println!("{}", output),
+                Err(err) => println!("{}", err),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The program: output the inputs (increased by 1) until the input is zero.
+    fn debugger() -> Debugger {
+        let code = intcode::asm::assemble(
+            "
+            loop:   in [value]
+                    jf [value], #end
+                    add [value], #1, [value]
+                    out [value]
+                    jt #1, #loop
+            end:    hlt
+            value:  db 0
+            ",
+        )
+        .unwrap();
+        Debugger::new(code)
+    }
+
+    #[test]
+    fn test_step_and_inspect() {
+        let mut debugger = debugger();
+        assert_eq!(
+            Ok("waiting for input\n    0: in [15]".to_string()),
+            debugger.execute("step")
+        );
+        debugger.execute("input 41").unwrap();
+        assert_eq!(Ok("    2: jf [15], #14".to_string()), debugger.execute("s"));
+        assert_eq!(Ok("   15: 41".to_string()), debugger.execute("x 15"));
+        debugger.execute("poke 15 0").unwrap();
+        assert_eq!(Ok("halted".to_string()), debugger.execute("continue"));
+    }
+
+    #[test]
+    fn test_breakpoints_and_watchpoints() {
+        let mut debugger = debugger();
+        debugger.execute("input 1 2 0").unwrap();
+        debugger.execute("break 9").unwrap();
+        assert_eq!(
+            Ok("breakpoint at 9\n    9: out [15]".to_string()),
+            debugger.execute("c")
+        );
+        assert_eq!(
+            Ok("output: 2\n   11: jt #1, #0".to_string()),
+            debugger.execute("next-io")
+        );
+        debugger.execute("delete 9").unwrap();
+        debugger.execute("watch 15").unwrap();
+        assert_eq!(
+            Ok("watchpoint at 15: 2 -> 3\n    9: out [15]".to_string()),
+            debugger.execute("c")
+        );
+        assert_eq!(
+            Ok("ip: 9\nrelative base: 0".to_string()),
+            debugger.execute("regs")
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        let mut debugger = debugger();
+        assert!(debugger.execute("jump 5").is_err());
+        assert!(debugger.execute("break").is_err());
+        assert_eq!(
+            Err("address out of range".to_string()),
+            debugger.execute(&format!("x {} 2", usize::MAX))
+        );
+        assert!(debugger
+            .execute(&format!("poke {} 1", usize::MAX))
+            .unwrap_err()
+            .contains("out of range"));
+        debugger.execute("poke 0 42").unwrap();
+        assert_eq!(
+            Ok(
+                "error: unknown opcode 42 at ip 0 (instruction 42, relative base 0)\n    0: db 42"
+                    .to_string()
+            ),
+            debugger.execute("s")
+        );
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let path =
+            env::temp_dir().join(format!("intcode-debugger-{}.snapshot", std::process::id()));
+        let path = path.to_str().unwrap();
+        let mut original = debugger();
+        original.execute("input 9").unwrap();
+        original.execute("step 3").unwrap();
+        assert_eq!(
+            Ok(format!("saved to {}", path)),
+            original.execute(&format!("save {}", path))
+        );
+        original.execute("c").unwrap();
+
+        let mut restored = debugger();
+        assert_eq!(
+            Ok("    9: out [15]".to_string()),
+            restored.execute(&format!("load {}", path))
+        );
+        assert_eq!(Ok("   15: 10".to_string()), restored.execute("x 15"));
+        fs::remove_file(path).unwrap();
+    }
+}
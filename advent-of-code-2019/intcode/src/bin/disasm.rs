@@ -0,0 +1,13 @@
+use std::env;
+
+// Prints the listing of the Intcode program read from the standard input.
+// With `--cfg` prints the summary of the static analysis, with `--dot` the control-flow graph
+// in the Graphviz DOT language.
+fn main() {
+    let code = intcode::load_code();
+    match env::args().nth(1).as_deref() {
+        Some("--cfg") => print!("{}", intcode::cfg::analyze(&code)),
+        Some("--dot") => print!("{}", intcode::cfg::analyze(&code).to_dot()),
+        _ => print!("{}", intcode::disasm::listing(&code)),
+    }
+}
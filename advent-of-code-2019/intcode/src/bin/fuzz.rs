@@ -0,0 +1,24 @@
+use intcode::fuzz::{self, Case};
+use std::env;
+use std::process;
+
+// Compares the interpreter with the reference one on random programs:
+//
+//     cargo run --release --bin fuzz [-- COUNT [FIRST_SEED]]
+//
+// Prints the first divergent case, which can be reproduced from its seed alone.
+fn main() {
+    let mut args = env::args()
+        .skip(1)
+        .map(|arg| arg.parse().expect("not a number"));
+    let count = args.next().unwrap_or(100_000);
+    let first_seed = args.next().unwrap_or(0);
+    match fuzz::fuzz(first_seed, count, 10_000) {
+        Ok(()) => println!("{} cases from seed {}: no divergence", count, first_seed),
+        Err(divergence) => {
+            println!("{}", divergence);
+            println!("{:?}", Case::generate(divergence.seed));
+            process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,16 @@
+use std::env;
+use std::process;
+
+// Translates the Intcode program read from the standard input to a Rust module, named after
+// the first argument (`program` by default).
+fn main() {
+    let code = intcode::load_code();
+    let name = env::args().nth(1).unwrap_or_else(|| "program".to_string());
+    match intcode::transpile::transpile(&code, &name) {
+        Ok(module) => print!("{}", module),
+        Err(err) => {
+            eprintln!("{}", err);
+            process::exit(1);
+        }
+    }
+}
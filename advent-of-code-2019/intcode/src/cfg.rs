@@ -0,0 +1,464 @@
+//! Static analysis of Intcode programs: the control-flow graph and what can be learned from it
+//! without running the program.
+//!
+//! The instructions are discovered by following the control flow from the address 0, so the
+//! data mixed with the code is never decoded as instructions (unlike the linear sweep of
+//! `disasm::disassemble`).
+//!
+//! The programs compiled to Intcode use the relative base as a stack pointer. A call stores
+//! the return address in a stack slot and jumps to the function:
+//!
+//! ```text
+//! add #ret, #0, rb+0
+//! jt #1, #function
+//! ret: ...
+//! ```
+//!
+//! and the function returns with a jump through the stack slot (`jf #0, rb+0`). The return
+//! addresses are recognized as the constants stored in the stack slots before a jump to
+//! the next instruction, and the functions passed around as values (stored in the stack
+//! slots) are recognized by their prologue: a stack allocation (`arb #n`).
+
+use super::disasm::{decode, Instruction, Param};
+use super::{Byte, Opcode};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::ops::Range;
+
+/// How the control leaves a basic block.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Exit {
+    /// Continues with the next block (which is the target of some jump).
+    Next(usize),
+    Jump(usize),
+    /// A conditional jump: `target` is `None` if it is computed at run time.
+    Branch {
+        target: Option<usize>,
+        next: usize,
+    },
+    /// A call of the function at `target`, returning to `ret`.
+    Call {
+        target: usize,
+        ret: usize,
+    },
+    /// A call of a function whose address is computed at run time.
+    IndirectCall {
+        ret: usize,
+    },
+    /// A jump through a stack slot.
+    Return,
+    /// A jump to an address computed at run time.
+    Indirect,
+    Halt,
+    /// The block runs into memory that doesn't decode as an instruction.
+    Invalid,
+}
+
+impl Exit {
+    pub fn successors(&self) -> Vec<usize> {
+        match *self {
+            Exit::Next(next) | Exit::Jump(next) => vec![next],
+            Exit::Branch { target, next } => target.into_iter().chain(Some(next)).collect(),
+            Exit::Call { target, ret } => vec![target, ret],
+            Exit::IndirectCall { ret } => vec![ret],
+            Exit::Return | Exit::Indirect | Exit::Halt | Exit::Invalid => Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Block {
+    pub start: usize,
+    /// One past the last cell of the last instruction.
+    pub end: usize,
+    pub instructions: Vec<(usize, Instruction)>,
+    pub exit: Exit,
+}
+
+/// A write of an instruction (at `at`) into a memory cell occupied by the code.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CodeWrite {
+    pub at: usize,
+    pub target: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cfg {
+    /// The basic blocks by their start address.
+    pub blocks: BTreeMap<usize, Block>,
+    /// The entry points of the functions.
+    pub functions: Vec<usize>,
+    /// The self-modifying writes (only the ones with the addresses known statically).
+    pub code_writes: Vec<CodeWrite>,
+    /// The memory regions that are never executed: data or dead code.
+    pub unreachable: Vec<Range<usize>>,
+    /// The addresses reached by the control flow that don't decode as instructions.
+    pub invalid: Vec<usize>,
+}
+
+fn to_address(value: Byte) -> Option<usize> {
+    if value < 0 {
+        None
+    } else {
+        Some(value as usize)
+    }
+}
+
+// The constant stored by the instruction into a stack slot, e.g. `add #ret, #0, rb+0`.
+fn stored_constant(instruction: &Instruction) -> Option<Byte> {
+    match instruction.params[..] {
+        [Param::Immediate(a), Param::Immediate(b), Param::Relative(_)] => {
+            match instruction.opcode {
+                Opcode::Add => a.checked_add(b),
+                Opcode::Multiply => a.checked_mul(b),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+// The exit of the instruction at the end of a block, `None` if the control goes to `next`.
+fn flow(instruction: &Instruction, next: usize, stored: &[Byte]) -> Option<Exit> {
+    let (condition, target) = match (instruction.opcode, &instruction.params[..]) {
+        (Opcode::Stop, _) => return Some(Exit::Halt),
+        (Opcode::JumpIfTrue, [condition, target]) => (condition, target),
+        (Opcode::JumpIfFalse, [condition, target]) => (condition, target),
+        _ => return None,
+    };
+    let always = match condition {
+        Param::Immediate(value) => {
+            Some((*value != 0) == (instruction.opcode == Opcode::JumpIfTrue))
+        }
+        _ => None,
+    };
+    let is_call = stored.contains(&(next as Byte));
+    let target = match target {
+        Param::Immediate(value) => to_address(*value),
+        _ => None,
+    };
+    let exit = match (always, target) {
+        (Some(false), _) => return None,
+        (Some(true), Some(target)) if is_call => Exit::Call { target, ret: next },
+        (Some(true), Some(target)) => Exit::Jump(target),
+        (Some(true), None) if is_call => Exit::IndirectCall { ret: next },
+        (Some(true), None) => match instruction.params[1] {
+            Param::Relative(_) => Exit::Return,
+            _ => Exit::Indirect,
+        },
+        (None, target) => Exit::Branch { target, next },
+    };
+    Some(exit)
+}
+
+fn is_prologue(code: &[Byte], address: usize) -> bool {
+    match decode(code, address) {
+        Some(Instruction {
+            opcode: Opcode::UpdateRelativeBase,
+            params,
+        }) => matches!(params[..], [Param::Immediate(size)] if size > 0),
+        None | Some(_) => false,
+    }
+}
+
+/// Recovers the control-flow graph of the program and analyzes it.
+pub fn analyze(code: &[Byte]) -> Cfg {
+    let mut instructions: BTreeMap<usize, Instruction> = BTreeMap::new();
+    let mut exits: BTreeMap<usize, Exit> = BTreeMap::new();
+    let mut leaders: BTreeSet<usize> = BTreeSet::new();
+    let mut functions: BTreeSet<usize> = BTreeSet::new();
+    let mut invalid: BTreeSet<usize> = BTreeSet::new();
+    let mut constants: BTreeSet<usize> = BTreeSet::new();
+
+    leaders.insert(0);
+    let mut worklist = vec![0];
+    loop {
+        while let Some(mut address) = worklist.pop() {
+            // The constants stored in the stack slots along the straight-line code.
+            let mut stored = Vec::new();
+            loop {
+                if instructions.contains_key(&address) {
+                    // The straight-line code joined the code discovered before.
+                    leaders.insert(address);
+                    break;
+                }
+                let instruction = match decode(code, address) {
+                    Some(instruction) => instruction,
+                    None => {
+                        invalid.insert(address);
+                        break;
+                    }
+                };
+                let next = address + instruction.size();
+                if let Some(value) = stored_constant(&instruction) {
+                    stored.push(value);
+                    constants.extend(to_address(value));
+                }
+                let exit = flow(&instruction, next, &stored);
+                instructions.insert(address, instruction);
+                match exit {
+                    Some(exit) => {
+                        if let Exit::Call { target, .. } = exit {
+                            functions.insert(target);
+                        }
+                        for successor in exit.successors() {
+                            leaders.insert(successor);
+                            worklist.push(successor);
+                        }
+                        exits.insert(address, exit);
+                        break;
+                    }
+                    None => address = next,
+                }
+            }
+        }
+        // The functions passed as values are explored once the direct control flow is.
+        let pointers: Vec<usize> = constants
+            .iter()
+            .copied()
+            .filter(|&address| !instructions.contains_key(&address) && is_prologue(code, address))
+            .collect();
+        if pointers.is_empty() {
+            break;
+        }
+        for address in pointers {
+            functions.insert(address);
+            leaders.insert(address);
+            worklist.push(address);
+        }
+    }
+
+    let mut blocks = BTreeMap::new();
+    for &start in leaders.iter() {
+        if !instructions.contains_key(&start) {
+            continue;
+        }
+        let mut block_instructions = Vec::new();
+        let mut address = start;
+        let exit = loop {
+            let instruction = &instructions[&address];
+            block_instructions.push((address, instruction.clone()));
+            if let Some(exit) = exits.get(&address) {
+                break *exit;
+            }
+            address += instruction.size();
+            if !instructions.contains_key(&address) {
+                break Exit::Invalid;
+            }
+            if leaders.contains(&address) {
+                break Exit::Next(address);
+            }
+        };
+        let (last, instruction) = block_instructions.last().unwrap();
+        let end = last + instruction.size();
+        blocks.insert(
+            start,
+            Block {
+                start,
+                end,
+                instructions: block_instructions,
+                exit,
+            },
+        );
+    }
+
+    let mut is_code = vec![false; code.len()];
+    for (&address, instruction) in instructions.iter() {
+        for cell in is_code[address..address + instruction.size()].iter_mut() {
+            *cell = true;
+        }
+    }
+    let mut code_writes = Vec::new();
+    for (&address, instruction) in instructions.iter() {
+        if let Some(operand) = instruction.opcode.output_operand() {
+            if let Param::Position(target) = instruction.params[operand - 1] {
+                match to_address(target) {
+                    Some(target) if target < code.len() && is_code[target] => {
+                        code_writes.push(CodeWrite {
+                            at: address,
+                            target,
+                        })
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+    let mut unreachable: Vec<Range<usize>> = Vec::new();
+    for address in (0..code.len()).filter(|&address| !is_code[address]) {
+        match unreachable.last_mut() {
+            Some(range) if range.end == address => range.end += 1,
+            _ => unreachable.push(address..address + 1),
+        }
+    }
+
+    Cfg {
+        blocks,
+        functions: functions.into_iter().collect(),
+        code_writes,
+        unreachable,
+        invalid: invalid.into_iter().collect(),
+    }
+}
+
+impl Cfg {
+    /// Renders the graph in the Graphviz DOT language: the function entries are drawn in bold,
+    /// the returns from the calls are dashed.
+    pub fn to_dot(&self) -> String {
+        let mut dot =
+            String::from("digraph intcode {\n    node [shape=box, fontname=monospace];\n");
+        for block in self.blocks.values() {
+            let label: String = block
+                .instructions
+                .iter()
+                .map(|(address, instruction)| format!("{}: {}\\l", address, instruction))
+                .collect();
+            let style = if self.functions.contains(&block.start) {
+                ", style=bold"
+            } else {
+                ""
+            };
+            dot += &format!("    b{} [label=\"{}\"{}];\n", block.start, label, style);
+        }
+        for block in self.blocks.values() {
+            let from = block.start;
+            let edges = match block.exit {
+                Exit::Next(next) | Exit::Jump(next) => vec![(next, "")],
+                Exit::Branch { target, next } => target
+                    .map(|target| (target, " [label=\"jump\"]"))
+                    .into_iter()
+                    .chain(Some((next, "")))
+                    .collect(),
+                Exit::Call { target, ret } => {
+                    vec![(target, " [label=\"call\"]"), (ret, " [style=dashed]")]
+                }
+                Exit::IndirectCall { ret } => vec![(ret, " [style=dashed]")],
+                Exit::Return | Exit::Indirect | Exit::Halt | Exit::Invalid => Vec::new(),
+            };
+            for (to, attributes) in edges {
+                if self.blocks.contains_key(&to) {
+                    dot += &format!("    b{} -> b{}{};\n", from, to, attributes);
+                }
+            }
+        }
+        dot += "}\n";
+        dot
+    }
+}
+
+impl fmt::Display for Cfg {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "blocks: {}", self.blocks.len())?;
+        let functions: Vec<_> = self.functions.iter().map(|f| f.to_string()).collect();
+        writeln!(f, "functions: {}", functions.join(", "))?;
+        writeln!(f, "self-modifying writes:")?;
+        for write in self.code_writes.iter() {
+            writeln!(f, "{:>5}: writes [{}]", write.at, write.target)?;
+        }
+        writeln!(f, "unreachable:")?;
+        for range in self.unreachable.iter() {
+            writeln!(f, "{:>5}: {} cells", range.start, range.len())?;
+        }
+        if !self.invalid.is_empty() {
+            let invalid: Vec<_> = self.invalid.iter().map(|a| a.to_string()).collect();
+            writeln!(f, "invalid instructions: {}", invalid.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::assemble;
+
+    #[test]
+    fn test_calls() {
+        let code = assemble(
+            "
+                    arb #100
+                    add #back, #0, rb+0
+                    add #5, #0, rb+1
+                    add #twice, #0, rb+2
+                    jt #1, #double
+            back:   out rb+1
+                    hlt
+            double: arb #2
+                    add rb-1, rb-1, rb-1
+                    arb #-2
+                    jf #0, rb+0
+            dead:   out #1
+                    hlt
+            twice:  arb #1
+                    arb #-1
+                    jt #1, rb+0
+            ",
+        )
+        .unwrap();
+        let cfg = analyze(&code);
+        let exits: Vec<_> = cfg.blocks.values().map(|b| (b.start, b.exit)).collect();
+        assert_eq!(
+            vec![
+                (
+                    0,
+                    Exit::Call {
+                        target: 20,
+                        ret: 17
+                    }
+                ),
+                (17, Exit::Halt),
+                (20, Exit::Return),
+                (34, Exit::Return),
+            ],
+            exits
+        );
+        assert_eq!(vec![20, 34], cfg.functions);
+        assert_eq!(vec![31..34], cfg.unreachable);
+        assert!(cfg.code_writes.is_empty() && cfg.invalid.is_empty());
+
+        let dot = cfg.to_dot();
+        assert!(dot.starts_with("digraph intcode {\n"));
+        assert!(dot.contains("    b20 [label=\"20: arb #2\\l22: add rb-1, rb-1, rb-1\\l"));
+        assert!(dot.contains("    b0 -> b20 [label=\"call\"];\n    b0 -> b17 [style=dashed];\n"));
+    }
+
+    #[test]
+    fn test_branches_and_code_writes() {
+        let code = assemble(
+            "
+            loop:   in [x]
+                    add [x], #0, [patch+1]
+            patch:  out #0
+                    jt [x], #loop
+                    hlt
+            x:      db 0
+            ",
+        )
+        .unwrap();
+        let cfg = analyze(&code);
+        assert_eq!(2, cfg.blocks.len());
+        assert_eq!(
+            Exit::Branch {
+                target: Some(0),
+                next: 11
+            },
+            cfg.blocks[&0].exit
+        );
+        assert_eq!(vec![CodeWrite { at: 2, target: 7 }], cfg.code_writes);
+        assert_eq!(vec![12..13], cfg.unreachable);
+        assert_eq!(
+            "blocks: 2\nfunctions: \nself-modifying writes:\n    2: writes [7]\n\
+             unreachable:\n   12: 1 cells\n",
+            cfg.to_string()
+        );
+    }
+
+    #[test]
+    fn test_invalid_code() {
+        // The jump skips the data and the code runs into an unknown opcode.
+        let cfg = analyze(&[1105, 1, 5, 7, 7, 1101, 1, 1, 3, 42]);
+        assert_eq!(vec![9], cfg.invalid);
+        assert_eq!(Exit::Invalid, cfg.blocks[&5].exit);
+        assert_eq!(vec![3..5, 9..10], cfg.unreachable);
+    }
+}
@@ -0,0 +1,226 @@
+//! Pipelines, rings and other graphs of Intcode machines.
+//!
+//! A `Circuit` describes the machines (the nodes), the inputs each one starts with and the
+//! connections between them: every output of a node is pushed to the inputs of all the nodes
+//! it's connected to, in the order of the connections. Running the circuit on a `Scheduler`
+//! reports what each node produced and whether it halted.
+//!
+//! The amplifiers of day 7 are a pipeline (the first part) and a ring (the second part):
+//!
+//! ```no_run
+//! # use intcode::circuit::Circuit;
+//! # let code = intcode::load_code();
+//! let mut circuit = Circuit::ring(&code, 5);
+//! for (node, phase) in [9, 8, 7, 6, 5].iter().enumerate() {
+//!     circuit.push_input(node, *phase);
+//! }
+//! circuit.push_input(0, 0);
+//! let report = circuit.run().unwrap();
+//! println!("{:?}", report.nodes[4].last_output());
+//! ```
+
+use super::scheduler::{Inputs, NodeError, Outcome, Scheduler, Topology};
+use super::{Byte, Intcode};
+
+pub struct Circuit {
+    machines: Vec<Intcode>,
+    inputs: Vec<Vec<Byte>>,
+    edges: Vec<Vec<usize>>,
+}
+
+/// What a node did during the run.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NodeReport {
+    pub outputs: Vec<Byte>,
+    pub halted: bool,
+}
+
+impl NodeReport {
+    pub fn last_output(&self) -> Option<Byte> {
+        self.outputs.last().copied()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Report {
+    pub outcome: Outcome,
+    pub nodes: Vec<NodeReport>,
+}
+
+/// Routes the outputs along the edges of a graph, keeping all of them.
+pub struct Graph {
+    edges: Vec<Vec<usize>>,
+    outputs: Vec<Vec<Byte>>,
+}
+
+impl Graph {
+    /// `edges[node]` are the nodes receiving the outputs of `node`.
+    pub fn new(edges: Vec<Vec<usize>>) -> Self {
+        let outputs = vec![Vec::new(); edges.len()];
+        Graph { edges, outputs }
+    }
+
+    pub fn outputs(&self, node: usize) -> &[Byte] {
+        &self.outputs[node]
+    }
+}
+
+impl Topology for Graph {
+    fn route(&mut self, from: usize, value: Byte, inputs: &mut Inputs) {
+        self.outputs[from].push(value);
+        for &to in self.edges[from].iter() {
+            inputs.push(to, value);
+        }
+    }
+}
+
+impl Circuit {
+    pub fn new() -> Self {
+        Circuit {
+            machines: Vec::new(),
+            inputs: Vec::new(),
+            edges: Vec::new(),
+        }
+    }
+
+    /// `size` copies of the program, each one connected to the next one.
+    pub fn pipeline(code: &[Byte], size: usize) -> Self {
+        let mut circuit = Circuit::new();
+        for node in 0..size {
+            circuit.add(Intcode::new(code.to_vec()));
+            if node > 0 {
+                circuit.connect(node - 1, node);
+            }
+        }
+        circuit
+    }
+
+    /// A pipeline with the last node connected back to the first one.
+    pub fn ring(code: &[Byte], size: usize) -> Self {
+        let mut circuit = Circuit::pipeline(code, size);
+        if size > 0 {
+            circuit.connect(size - 1, 0);
+        }
+        circuit
+    }
+
+    /// Adds a machine and returns its node index.
+    pub fn add(&mut self, machine: Intcode) -> usize {
+        self.machines.push(machine);
+        self.inputs.push(Vec::new());
+        self.edges.push(Vec::new());
+        self.machines.len() - 1
+    }
+
+    /// Sends the outputs of the node `from` to the node `to`.
+    pub fn connect(&mut self, from: usize, to: usize) {
+        assert!(to < self.machines.len(), "no node {}", to);
+        self.edges[from].push(to);
+    }
+
+    /// Adds an initial input of the node, read before anything sent by other nodes.
+    pub fn push_input(&mut self, node: usize, value: Byte) {
+        self.inputs[node].push(value);
+    }
+
+    /// Runs the machines until none of them can make progress: the outcome tells whether all
+    /// of them halted or some are waiting for inputs.
+    pub fn run(self) -> Result<Report, NodeError> {
+        let size = self.machines.len();
+        let mut scheduler = Scheduler::new(Graph::new(self.edges));
+        for (machine, inputs) in self.machines.into_iter().zip(self.inputs) {
+            let node = scheduler.add(machine);
+            for value in inputs {
+                scheduler.push_input(node, value);
+            }
+        }
+        let outcome = scheduler.run()?;
+        let halted: Vec<bool> = (0..size).map(|node| scheduler.is_halted(node)).collect();
+        let graph = scheduler.into_topology();
+        let nodes = graph
+            .outputs
+            .into_iter()
+            .zip(halted)
+            .map(|(outputs, halted)| NodeReport { outputs, halted })
+            .collect();
+        Ok(Report { outcome, nodes })
+    }
+}
+
+impl Default for Circuit {
+    fn default() -> Self {
+        Circuit::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The day 7 examples.
+    const AMPLIFIER: [Byte; 17] = [
+        3, 15, 3, 16, 1002, 16, 10, 16, 1, 16, 15, 15, 4, 15, 99, 0, 0,
+    ];
+    const FEEDBACK: [Byte; 29] = [
+        3, 26, 1001, 26, -4, 26, 3, 27, 1002, 27, 2, 27, 1, 27, 26, 27, 4, 27, 1001, 28, -1, 28,
+        1005, 28, 6, 99, 0, 0, 5,
+    ];
+
+    fn with_phases(mut circuit: Circuit, phases: &[Byte]) -> Circuit {
+        for (node, &phase) in phases.iter().enumerate() {
+            circuit.push_input(node, phase);
+        }
+        circuit.push_input(0, 0);
+        circuit
+    }
+
+    #[test]
+    fn test_pipeline() {
+        let circuit = with_phases(Circuit::pipeline(&AMPLIFIER, 5), &[4, 3, 2, 1, 0]);
+        let report = circuit.run().unwrap();
+        assert_eq!(Outcome::Halted, report.outcome);
+        assert_eq!(Some(43210), report.nodes[4].last_output());
+        assert_eq!(vec![4], report.nodes[0].outputs);
+    }
+
+    #[test]
+    fn test_ring() {
+        let circuit = with_phases(Circuit::ring(&FEEDBACK, 5), &[9, 8, 7, 6, 5]);
+        let report = circuit.run().unwrap();
+        assert_eq!(Outcome::Halted, report.outcome);
+        assert_eq!(Some(139629729), report.nodes[4].last_output());
+        assert!(report.nodes.iter().all(|node| node.halted));
+    }
+
+    #[test]
+    fn test_dag() {
+        // The program: output the sum of two inputs.
+        let adder = || Intcode::new(vec![3, 11, 3, 12, 1, 11, 12, 11, 4, 11, 99, 0, 0]);
+        // The program: output the input twice.
+        let splitter = || Intcode::new(vec![3, 7, 4, 7, 4, 7, 99, 0]);
+        let mut circuit = Circuit::new();
+        let source = circuit.add(splitter());
+        let left = circuit.add(adder());
+        let right = circuit.add(adder());
+        let sink = circuit.add(adder());
+        // A waiting node: it never gets an input.
+        let waiting = circuit.add(adder());
+        circuit.connect(source, left);
+        circuit.connect(source, right);
+        circuit.connect(left, sink);
+        circuit.connect(right, sink);
+        circuit.push_input(source, 5);
+        circuit.push_input(right, 100);
+        let report = circuit.run().unwrap();
+        assert_eq!(Outcome::Deadlock, report.outcome);
+        assert_eq!(vec![5, 5], report.nodes[source].outputs);
+        assert_eq!(vec![10], report.nodes[left].outputs);
+        assert_eq!(vec![105], report.nodes[right].outputs);
+        assert_eq!(vec![115], report.nodes[sink].outputs);
+        assert!(report.nodes[sink].halted);
+        // The second 5 sent to the right adder is never read.
+        assert!(report.nodes[right].halted);
+        assert!(!report.nodes[waiting].halted);
+        assert_eq!(None, report.nodes[waiting].last_output());
+    }
+}
@@ -0,0 +1,401 @@
+// The conformance suite: every opcode in every parameter mode, the relative base, large values
+// and the memory growth. Each case runs on the interpreter with both memory backends, with and
+// without the instruction cache, and on the reference interpreter.
+
+use super::memory::{Memory, PagedMemory};
+use super::reference::Reference;
+use super::{Byte, ErrorKind, Intcode, State};
+
+#[derive(Debug, Clone, PartialEq)]
+enum End {
+    Halted,
+    NeedsInput,
+    Failed { kind: ErrorKind, ip: usize },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Run {
+    outputs: Vec<Byte>,
+    end: End,
+    memory: Vec<Byte>,
+}
+
+struct Case {
+    code: Vec<Byte>,
+    inputs: Vec<Byte>,
+    max_address: Option<usize>,
+    checked: bool,
+}
+
+fn case(code: &[Byte]) -> Case {
+    Case {
+        code: code.to_vec(),
+        inputs: Vec::new(),
+        max_address: None,
+        checked: false,
+    }
+}
+
+const FUEL: usize = 10_000;
+
+fn run_machine<M: Memory>(case: &Case, mut machine: Intcode<M>, cache: bool) -> Run {
+    machine.set_instruction_cache(cache);
+    machine.set_checked_arithmetic(case.checked);
+    if let Some(max_address) = case.max_address {
+        machine.set_max_address(max_address);
+    }
+    case.inputs
+        .iter()
+        .for_each(|&input| machine.push_input(input));
+    let mut outputs = Vec::new();
+    for _ in 0..FUEL {
+        let end = match machine.step() {
+            Ok(Some(State::Output(value))) => {
+                outputs.push(value);
+                continue;
+            }
+            Ok(None) => continue,
+            Ok(Some(State::Halted)) => End::Halted,
+            Ok(Some(State::NeedsInput)) => End::NeedsInput,
+            Err(err) => End::Failed {
+                kind: err.kind,
+                ip: err.ip,
+            },
+        };
+        return Run {
+            outputs,
+            end,
+            memory: (0..machine.memory().len())
+                .map(|address| machine.memory().read(address))
+                .collect(),
+        };
+    }
+    panic!("out of fuel");
+}
+
+fn run_reference(case: &Case) -> Run {
+    let mut machine = Reference::new(case.code.to_vec());
+    machine.checked_arithmetic = case.checked;
+    if let Some(max_address) = case.max_address {
+        machine.max_address = max_address;
+    }
+    machine.inputs.extend(case.inputs.iter());
+    let mut outputs = Vec::new();
+    for _ in 0..FUEL {
+        let end = match machine.step() {
+            Ok(Some(State::Output(value))) => {
+                outputs.push(value);
+                continue;
+            }
+            Ok(None) => continue,
+            Ok(Some(State::Halted)) => End::Halted,
+            Ok(Some(State::NeedsInput)) => End::NeedsInput,
+            Err(kind) => End::Failed {
+                kind,
+                ip: machine.ip,
+            },
+        };
+        return Run {
+            outputs,
+            end,
+            memory: machine.memory,
+        };
+    }
+    panic!("out of fuel");
+}
+
+impl Case {
+    fn inputs(mut self, inputs: &[Byte]) -> Self {
+        self.inputs = inputs.to_vec();
+        self
+    }
+
+    fn max_address(mut self, max_address: usize) -> Self {
+        self.max_address = Some(max_address);
+        self
+    }
+
+    fn checked(mut self) -> Self {
+        self.checked = true;
+        self
+    }
+
+    // Runs the case on all the interpreters, checking they agree.
+    fn run(&self) -> Run {
+        let run = run_reference(self);
+        let runs = [
+            run_machine(self, Intcode::new(self.code.to_vec()), true),
+            run_machine(self, Intcode::new(self.code.to_vec()), false),
+            run_machine(
+                self,
+                Intcode::with_memory(PagedMemory::from_vec(self.code.to_vec())),
+                true,
+            ),
+        ];
+        for other in runs.iter() {
+            assert_eq!(run, *other, "{:?}", self.code);
+        }
+        run
+    }
+
+    fn outputs(&self, outputs: &[Byte]) -> Run {
+        let run = self.run();
+        assert_eq!(End::Halted, run.end, "{:?}", self.code);
+        assert_eq!(outputs, &run.outputs[..], "{:?}", self.code);
+        run
+    }
+
+    fn fails(&self, kind: ErrorKind, ip: usize) -> Run {
+        let run = self.run();
+        assert_eq!(End::Failed { kind, ip }, run.end, "{:?}", self.code);
+        run
+    }
+}
+
+// The program prefix setting the relative base to 20: the operands of the tested instruction
+// (at 2) are the values at 30 and 31, stored at the position 30 or the relative offset 10
+// (with the mode 0, 1 or 2), and the result goes to 32 (offset 12).
+fn with_operands(instruction: &[Byte], a: Byte, b: Byte) -> Vec<Byte> {
+    let mut code = vec![109, 20];
+    code.extend_from_slice(instruction);
+    code.extend_from_slice(&[4, 32, 99]);
+    code.resize(30, 0);
+    code.extend_from_slice(&[a, b, -1]);
+    code
+}
+
+// The parameter referring to the value at `address` in `mode`.
+fn parameter(mode: Byte, address: usize, value: Byte) -> Byte {
+    match mode {
+        0 => address as Byte,
+        1 => value,
+        _ => address as Byte - 20,
+    }
+}
+
+type Operation = fn(Byte, Byte) -> Byte;
+
+#[test]
+fn test_binary_instructions() {
+    let operations: [(Byte, Operation); 4] = [
+        (1, |a, b| a + b),
+        (2, |a, b| a * b),
+        (7, |a, b| (a < b) as Byte),
+        (8, |a, b| (a == b) as Byte),
+    ];
+    for &(opcode, operation) in operations.iter() {
+        for &(a, b) in [(7, 5), (5, 7), (-3, -3), (0, 9)].iter() {
+            for modes in 0..27 {
+                let (m1, m2, m3) = (modes % 3, modes / 3 % 3, modes / 9);
+                let instruction = [
+                    opcode + m1 * 100 + m2 * 1000 + m3 * 10000,
+                    parameter(m1, 30, a),
+                    parameter(m2, 31, b),
+                    parameter(m3, 32, 0),
+                ];
+                let case = case(&with_operands(&instruction, a, b));
+                if m3 == 1 {
+                    case.fails(ErrorKind::WriteInImmediateMode { operand: 3 }, 2);
+                } else {
+                    let run = case.outputs(&[operation(a, b)]);
+                    assert_eq!(operation(a, b), run.memory[32]);
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn test_input_output() {
+    for &mode in [0, 2].iter() {
+        let instruction = [3 + mode * 100, parameter(mode, 32, 0)];
+        let code = with_operands(&instruction, 0, 0);
+        case(&code).inputs(&[42]).outputs(&[42]);
+        assert_eq!(End::NeedsInput, case(&code).run().end);
+    }
+    case(&with_operands(&[103, 32], 0, 0))
+        .inputs(&[42])
+        .fails(ErrorKind::WriteInImmediateMode { operand: 1 }, 2);
+
+    for &mode in [0, 1, 2].iter() {
+        let instruction = [4 + mode * 100, parameter(mode, 30, 17)];
+        case(&with_operands(&instruction, 17, 0)).outputs(&[17, -1]);
+    }
+}
+
+#[test]
+fn test_jumps() {
+    // The jumps to 9 output 1, falling through outputs 0.
+    for &(opcode, jumps_if) in [(5, true), (6, false)].iter() {
+        for &condition in [0, 1, -5].iter() {
+            for modes in 0..9 {
+                let (m1, m2) = (modes % 3, modes / 3);
+                let mut code = vec![
+                    109,
+                    20,
+                    opcode + m1 * 100 + m2 * 1000,
+                    parameter(m1, 30, condition),
+                    parameter(m2, 31, 9),
+                    104,
+                    0,
+                    99,
+                    99,
+                    104,
+                    1,
+                    99,
+                ];
+                code.resize(30, 0);
+                code.extend_from_slice(&[condition, 9]);
+                let jumped = (condition != 0) == jumps_if;
+                case(&code).outputs(&[jumped as Byte]);
+            }
+        }
+    }
+    case(&[1105, 1, -1]).fails(
+        ErrorKind::NegativeAddress {
+            operand: 2,
+            address: -1,
+        },
+        0,
+    );
+    case(&[1106, 0, 100]).max_address(50).fails(
+        ErrorKind::AddressOutOfRange {
+            operand: 2,
+            address: 100,
+        },
+        0,
+    );
+    // Jumping past the end of the program reads the instruction 0.
+    case(&[1105, 1, 100]).fails(ErrorKind::UnknownOpcode(0), 100);
+}
+
+#[test]
+fn test_relative_base() {
+    for &mode in [0, 1, 2].iter() {
+        // Moves the relative base by 3 (from 20) and outputs the value at 23 + 7.
+        let instruction = [9 + mode * 100, parameter(mode, 30, 3), 204, 7];
+        let mut code = with_operands(&instruction, 3, 0);
+        code[6] = 99;
+        case(&code).outputs(&[3]);
+    }
+    // A negative relative base with positive offsets.
+    case(&[109, -5, 204, 15, 99, 0, 0, 0, 0, 0, 77]).outputs(&[77]);
+    // A negative relative address.
+    case(&[109, -5, 204, 4, 99]).fails(
+        ErrorKind::NegativeAddress {
+            operand: 1,
+            address: -1,
+        },
+        2,
+    );
+    // The relative base accumulates, including through negative values.
+    case(&[109, 10, 109, -3, 109, 1, 204, 0, 99]).outputs(&[99]);
+    case(&[109, 7, 109, -7, 204, 0, 99]).outputs(&[109]);
+    // An overflowing relative address, and relative base with the checked arithmetic.
+    case(&[109, 1, 204, Byte::MAX, 99]).fails(ErrorKind::Overflow, 2);
+    case(&[109, Byte::MAX, 109, 1, 99]).outputs(&[]);
+    case(&[109, Byte::MAX, 109, 1, 99])
+        .checked()
+        .fails(ErrorKind::Overflow, 2);
+    // Writes in the relative mode.
+    case(&[109, 10, 21101, 2, 3, 0, 4, 10, 99, 0, 0]).outputs(&[5]);
+    case(&[109, 7, 203, 0, 4, 7, 99, 0])
+        .inputs(&[-8])
+        .outputs(&[-8]);
+}
+
+#[test]
+fn test_large_values() {
+    let large = Byte::MAX / 2;
+    case(&[1102, large, 3, 7, 4, 7, 99, 0]).outputs(&[large.wrapping_mul(3)]);
+    case(&[1102, large, 3, 7, 4, 7, 99, 0])
+        .checked()
+        .fails(ErrorKind::Overflow, 0);
+    case(&[1101, Byte::MAX, 1, 7, 4, 7, 99, 0]).outputs(&[Byte::MIN]);
+    case(&[1101, Byte::MIN, -1, 7, 4, 7, 99, 0])
+        .checked()
+        .fails(ErrorKind::Overflow, 0);
+    case(&[1101, Byte::MIN, Byte::MAX, 7, 4, 7, 99, 0]).outputs(&[-1]);
+    case(&[1107, Byte::MIN, Byte::MAX, 7, 4, 7, 99, 0]).outputs(&[1]);
+    case(&[1108, Byte::MIN, Byte::MIN, 7, 4, 7, 99, 0]).outputs(&[1]);
+    case(&[104, Byte::MIN, 104, Byte::MAX, 99]).outputs(&[Byte::MIN, Byte::MAX]);
+    case(&[3, 5, 4, 5, 99, 0])
+        .inputs(&[Byte::MAX])
+        .outputs(&[Byte::MAX]);
+}
+
+#[test]
+fn test_memory_growth() {
+    // Writes past the end grow the memory, reads past the end give zero.
+    let run = case(&[1101, 4, 5, 1000, 4, 1000, 4, 2000, 99]).outputs(&[9, 0]);
+    assert_eq!(1001, run.memory.len());
+    case(&[21101, 4, 5, 1000, 204, 1000, 99]).outputs(&[9]);
+    case(&[3, 4000, 4, 4000, 99])
+        .inputs(&[6])
+        .max_address(4000)
+        .outputs(&[6]);
+    case(&[3, 4001, 99]).inputs(&[6]).max_address(4000).fails(
+        ErrorKind::AddressOutOfRange {
+            operand: 1,
+            address: 4001,
+        },
+        0,
+    );
+    case(&[4, -3, 99]).fails(
+        ErrorKind::NegativeAddress {
+            operand: 1,
+            address: -3,
+        },
+        0,
+    );
+}
+
+#[test]
+fn test_invalid_instructions() {
+    case(&[0]).fails(ErrorKind::UnknownOpcode(0), 0);
+    case(&[42, 0, 0, 0]).fails(ErrorKind::UnknownOpcode(42), 0);
+    case(&[-1]).fails(ErrorKind::UnknownOpcode(-1), 0);
+    case(&[-99]).fails(ErrorKind::UnknownOpcode(-99), 0);
+    case(&[301, 0, 0, 0, 99]).fails(
+        ErrorKind::InvalidMode {
+            operand: 1,
+            mode: 3,
+        },
+        0,
+    );
+    case(&[90001, 0, 0, 0, 99]).fails(
+        ErrorKind::InvalidMode {
+            operand: 3,
+            mode: 9,
+        },
+        0,
+    );
+    // The modes past the arity are ignored.
+    case(&[99999]).outputs(&[]);
+    case(&[9104, 5, 99]).outputs(&[5]);
+}
+
+#[test]
+fn test_self_modification() {
+    // The first instruction turns the `hlt` at 4 into `out 0`.
+    case(&[1101, 4, 0, 4, 99, 0, 99]).outputs(&[1101]);
+    // An executed instruction is patched into `hlt` before the loop runs it again.
+    case(&[104, 1, 1101, 99, 0, 0, 1105, 1, 0]).outputs(&[1]);
+    // An instruction overwriting itself keeps running from the next one.
+    case(&[1101, 99, 0, 0, 104, 1, 1105, 1, 0]).outputs(&[1]);
+    // The input rewrites the instruction it jumps to.
+    case(&[3, 5, 1105, 1, 5, 0, 7, 99])
+        .inputs(&[104])
+        .outputs(&[7]);
+}
+
+#[test]
+fn test_halt() {
+    // The machine stays on the `hlt`.
+    let mut machine = Intcode::new(vec![104, 1, 99]);
+    assert_eq!(Ok(State::Output(1)), machine.resume());
+    assert_eq!(Ok(State::Halted), machine.resume());
+    assert_eq!(Ok(State::Halted), machine.resume());
+    assert_eq!(2, machine.ip());
+    assert_eq!(1, machine.executed());
+}
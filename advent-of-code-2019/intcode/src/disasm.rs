@@ -0,0 +1,184 @@
+//! Disassembler of Intcode programs.
+//!
+//! The listing uses the following syntax for the operands: `[addr]` for the position mode,
+//! `#imm` for the immediate mode and `rb+off` for the relative mode.
+
+use super::{parse_instruction, Byte, Mode, Opcode};
+use std::fmt;
+
+// The maximum number of values in one data line of the listing.
+const DATA_LINE_LEN: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Param {
+    Position(Byte),
+    Immediate(Byte),
+    Relative(Byte),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Instruction {
+    pub opcode: Opcode,
+    pub params: Vec<Param>,
+}
+
+impl Instruction {
+    /// The number of memory cells occupied by the instruction.
+    pub fn size(&self) -> usize {
+        1 + self.params.len()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Line {
+    Instruction(usize, Instruction),
+    /// A region that doesn't decode as instructions.
+    Data(usize, Vec<Byte>),
+}
+
+/// Decodes the instruction at `address`, if the memory there looks like a valid instruction:
+/// known opcode, valid modes, no extra mode digits, no writes in the immediate mode and
+/// all the operands within the code.
+pub fn decode(code: &[Byte], address: usize) -> Option<Instruction> {
+    let (opcode, mut modes) = parse_instruction(*code.get(address)?).ok()?;
+    let mut params = Vec::with_capacity(opcode.arity());
+    for _ in 0..opcode.arity() {
+        let (operand, mode) = modes.next().ok()?;
+        let value = *code.get(address + operand)?;
+        let param = match mode {
+            Mode::Position => Param::Position(value),
+            Mode::Immediate if opcode.output_operand() == Some(operand) => return None,
+            Mode::Immediate => Param::Immediate(value),
+            Mode::Relative => Param::Relative(value),
+        };
+        params.push(param);
+    }
+    if modes.value != 0 {
+        return None;
+    }
+    Some(Instruction { opcode, params })
+}
+
+/// Splits the program into instructions and data regions with a linear sweep.
+pub fn disassemble(code: &[Byte]) -> Vec<Line> {
+    let mut lines = Vec::new();
+    let mut data: Vec<Byte> = Vec::new();
+    let mut address = 0;
+    while address < code.len() {
+        match decode(code, address) {
+            Some(instruction) => {
+                if !data.is_empty() {
+                    lines.push(Line::Data(address - data.len(), data));
+                    data = Vec::new();
+                }
+                let size = instruction.size();
+                lines.push(Line::Instruction(address, instruction));
+                address += size;
+            }
+            None => {
+                if data.len() == DATA_LINE_LEN {
+                    lines.push(Line::Data(address - data.len(), data));
+                    data = Vec::new();
+                }
+                data.push(code[address]);
+                address += 1;
+            }
+        }
+    }
+    if !data.is_empty() {
+        lines.push(Line::Data(address - data.len(), data));
+    }
+    lines
+}
+
+/// Renders the whole program as a readable listing, one line per instruction or data chunk.
+pub fn listing(code: &[Byte]) -> String {
+    disassemble(code)
+        .iter()
+        .map(|line| format!("{}\n", line))
+        .collect()
+}
+
+impl fmt::Display for Param {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Param::Position(address) => write!(f, "[{}]", address),
+            Param::Immediate(value) => write!(f, "#{}", value),
+            Param::Relative(offset) if *offset < 0 => write!(f, "rb{}", offset),
+            Param::Relative(offset) => write!(f, "rb+{}", offset),
+        }
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.opcode.mnemonic())?;
+        for (i, param) in self.params.iter().enumerate() {
+            let separator = if i == 0 { " " } else { ", " };
+            write!(f, "{}{}", separator, param)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Line {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Line::Instruction(address, instruction) => write!(f, "{:>5}: {}", address, instruction),
+            Line::Data(address, values) => {
+                let values: Vec<_> = values.iter().map(|value| value.to_string()).collect();
+                write!(f, "{:>5}: db {}", address, values.join(", "))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode() {
+        let code = vec![1002, 4, 3, 4, 99];
+        assert_eq!(
+            Some(Instruction {
+                opcode: Opcode::Multiply,
+                params: vec![Param::Position(4), Param::Immediate(3), Param::Position(4)],
+            }),
+            decode(&code, 0)
+        );
+        // Writes in immediate mode, unknown modes or truncated instructions are not decoded.
+        assert_eq!(None, decode(&[11101, 1, 1, 0], 0));
+        assert_eq!(None, decode(&[301, 1, 1, 0], 0));
+        assert_eq!(None, decode(&[100004, 1], 0));
+        assert_eq!(None, decode(&[1, 1, 1], 0));
+    }
+
+    #[test]
+    fn test_listing() {
+        let code = vec![
+            109, 1, 204, -1, 1001, 100, 1, 100, 1008, 100, 16, 101, 1006, 101, 0, 99, -7, 42,
+        ];
+        assert_eq!(
+            "    0: arb #1
+    2: out rb-1
+    4: add [100], #1, [100]
+    8: eq [100], #16, [101]
+   12: jf [101], #0
+   15: hlt
+   16: db -7, 42
+",
+            listing(&code)
+        );
+    }
+
+    #[test]
+    fn test_data_lines_are_split() {
+        let code: Vec<Byte> = (0..10).map(|_| -1).collect();
+        let lines = disassemble(&code);
+        assert_eq!(
+            vec![Line::Data(0, vec![-1; 8]), Line::Data(8, vec![-1; 2])],
+            lines
+        );
+    }
+}
@@ -0,0 +1,298 @@
+//! User-defined instructions.
+//!
+//! An `Extension` registered on the machine with `Intcode::register_opcode` handles the
+//! instructions with its opcode: the machine resolves the operands according to their modes
+//! (like for the built-in instructions) and the handler gets them in a `Context`, together
+//! with the access to the memory, the relative base and the queued inputs.
+//!
+//! The machines with extensions can't be traced (see `Intcode::step_traced`).
+
+use super::memory::Memory;
+use super::{check_address, Byte, Decoded, ErrorKind};
+use std::collections::VecDeque;
+
+pub trait Extension: Send + Sync {
+    /// The number of operands.
+    fn arity(&self) -> usize;
+
+    /// The (1-based) index of the operand the instruction writes to, if any: it is resolved to
+    /// an address instead of a value and can't be in the immediate mode.
+    fn output_operand(&self) -> Option<usize> {
+        None
+    }
+
+    fn execute(&self, context: &mut Context) -> Result<Effect, ErrorKind>;
+}
+
+/// What the machine does after an extension instruction.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Effect {
+    /// Continues with the next instruction.
+    Next,
+    /// Continues at the address: the machine fails with `ErrorKind::AddressOutOfRange` if it's
+    /// above the maximum address.
+    Jump(usize),
+    /// Stops with `State::NeedsInput`: the instruction is executed again once resumed, so it
+    /// must not consume any input before returning this.
+    NeedsInput,
+    /// Stops with `State::Output` and continues with the next instruction once resumed.
+    Output(Byte),
+    Halt,
+}
+
+/// The state of the machine given to an extension.
+pub struct Context<'a> {
+    ip: usize,
+    operands: &'a [Byte],
+    max_address: usize,
+    memory: &'a mut dyn Memory,
+    cache: &'a mut Option<Vec<Option<Decoded>>>,
+    relative_base: &'a mut Byte,
+    input: &'a mut VecDeque<Byte>,
+}
+
+impl<'a> Context<'a> {
+    pub(crate) fn new(
+        ip: usize,
+        operands: &'a [Byte],
+        max_address: usize,
+        memory: &'a mut dyn Memory,
+        cache: &'a mut Option<Vec<Option<Decoded>>>,
+        relative_base: &'a mut Byte,
+        input: &'a mut VecDeque<Byte>,
+    ) -> Self {
+        Context {
+            ip,
+            operands,
+            max_address,
+            memory,
+            cache,
+            relative_base,
+            input,
+        }
+    }
+
+    /// The address of the executed instruction.
+    pub fn ip(&self) -> usize {
+        self.ip
+    }
+
+    /// The values of the operands, with the output operand resolved to its address.
+    pub fn operands(&self) -> &[Byte] {
+        self.operands
+    }
+
+    pub fn read(&self, address: usize) -> Byte {
+        self.memory.read(address)
+    }
+
+    /// Fails with `ErrorKind::AddressOutOfRange` above the maximum address of the machine, like
+    /// the writes of the built-in instructions.
+    pub fn write(&mut self, address: usize, value: Byte) -> Result<(), ErrorKind> {
+        check_address(address, self.max_address)?;
+        if let Some(entry) = self.cache.as_mut().and_then(|cache| cache.get_mut(address)) {
+            *entry = None;
+        }
+        self.memory.write(address, value);
+        Ok(())
+    }
+
+    pub fn relative_base(&self) -> Byte {
+        *self.relative_base
+    }
+
+    pub fn set_relative_base(&mut self, relative_base: Byte) {
+        *self.relative_base = relative_base;
+    }
+
+    pub fn queued_inputs(&self) -> usize {
+        self.input.len()
+    }
+
+    /// Takes the next queued input, if any.
+    pub fn input(&mut self) -> Option<Byte> {
+        self.input.pop_front()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Intcode, RuntimeError, State};
+    use std::sync::{Arc, Mutex};
+
+    // Prints (collects) the value of its operand.
+    struct DebugPrint {
+        printed: Mutex<Vec<Byte>>,
+    }
+
+    impl Extension for DebugPrint {
+        fn arity(&self) -> usize {
+            1
+        }
+
+        fn execute(&self, context: &mut Context) -> Result<Effect, ErrorKind> {
+            self.printed.lock().unwrap().push(context.operands()[0]);
+            Ok(Effect::Next)
+        }
+    }
+
+    // Calls the host function with the number given by the first operand on the second one,
+    // writing the result into the third one.
+    struct HostCall;
+
+    impl Extension for HostCall {
+        fn arity(&self) -> usize {
+            3
+        }
+
+        fn output_operand(&self) -> Option<usize> {
+            Some(3)
+        }
+
+        fn execute(&self, context: &mut Context) -> Result<Effect, ErrorKind> {
+            let (function, argument, address) = match *context.operands() {
+                [function, argument, address] => (function, argument, address as usize),
+                _ => unreachable!(),
+            };
+            let result = match function {
+                0 => argument.abs(),
+                1 => argument.pow(2),
+                _ => return Err(ErrorKind::UnknownOpcode(function)),
+            };
+            context.write(address, result)?;
+            Ok(Effect::Next)
+        }
+    }
+
+    // Reads two inputs, outputs their sum and moves the relative base by it.
+    struct InputSum;
+
+    impl Extension for InputSum {
+        fn arity(&self) -> usize {
+            0
+        }
+
+        fn execute(&self, context: &mut Context) -> Result<Effect, ErrorKind> {
+            if context.queued_inputs() < 2 {
+                return Ok(Effect::NeedsInput);
+            }
+            let sum = context.input().unwrap() + context.input().unwrap();
+            let relative_base = context.relative_base();
+            context.set_relative_base(relative_base + sum);
+            Ok(Effect::Output(sum))
+        }
+    }
+
+    // Writes the second operand at the address given by the first one, then jumps to the
+    // third one.
+    struct StoreAndJump;
+
+    impl Extension for StoreAndJump {
+        fn arity(&self) -> usize {
+            3
+        }
+
+        fn execute(&self, context: &mut Context) -> Result<Effect, ErrorKind> {
+            let (address, value, target) = match *context.operands() {
+                [address, value, target] => (address as usize, value, target as usize),
+                _ => unreachable!(),
+            };
+            context.write(address, value)?;
+            Ok(Effect::Jump(target))
+        }
+    }
+
+    #[test]
+    fn test_extensions() {
+        let debug = Arc::new(DebugPrint {
+            printed: Mutex::new(Vec::new()),
+        });
+        // The program: print #7 and [0], store abs(-5) at 15 and square it in place, output it.
+        let mut machine = Intcode::new(vec![
+            1150, 7, 50, 0, 1151, 0, -5, 15, 151, 1, 15, 15, 4, 15, 99, 0,
+        ]);
+        machine.register_opcode(50, debug.clone());
+        machine.register_opcode(51, Arc::new(HostCall));
+        assert_eq!(Ok(State::Output(25)), machine.resume());
+        assert_eq!(Ok(State::Halted), machine.resume());
+        assert_eq!(vec![7, 1150], *debug.printed.lock().unwrap());
+
+        let mut machine = Intcode::new(vec![52, 52, 204, 0, 99]);
+        machine.register_opcode(52, Arc::new(InputSum));
+        machine.push_input(1);
+        assert_eq!(Ok(State::NeedsInput), machine.resume());
+        machine.push_input(2);
+        assert_eq!(Ok(State::Output(3)), machine.resume());
+        machine.push_input(-3);
+        machine.push_input(4);
+        assert_eq!(Ok(State::Output(1)), machine.resume());
+        // The relative base is now 4: `out rb+0` outputs the `hlt`.
+        assert_eq!(Ok(State::Output(99)), machine.resume());
+        assert_eq!(3, machine.executed());
+    }
+
+    #[test]
+    fn test_extension_errors() {
+        let mut machine = Intcode::new(vec![10151, 0, 1, 2, 99]);
+        machine.register_opcode(51, Arc::new(HostCall));
+        let err: RuntimeError = machine.resume().unwrap_err();
+        assert_eq!(ErrorKind::WriteInImmediateMode { operand: 3 }, err.kind);
+
+        let mut machine = Intcode::new(vec![1151, 7, 1, 0, 99]);
+        machine.register_opcode(51, Arc::new(HostCall));
+        assert_eq!(
+            ErrorKind::UnknownOpcode(7),
+            machine.resume().unwrap_err().kind
+        );
+
+        // The writes and the jumps of the extensions are limited to the maximum address.
+        let run = |code: Vec<Byte>| {
+            let mut machine = Intcode::new(code);
+            machine.register_opcode(53, Arc::new(StoreAndJump));
+            machine.set_max_address(9);
+            machine.resume()
+        };
+        assert_eq!(Ok(State::Halted), run(vec![11153, 9, 99, 9]));
+        assert_eq!(
+            ErrorKind::AddressOutOfRange {
+                operand: 0,
+                address: 10
+            },
+            run(vec![11153, 10, 99, 0]).unwrap_err().kind
+        );
+        assert_eq!(
+            ErrorKind::AddressOutOfRange {
+                operand: 0,
+                address: 10
+            },
+            run(vec![11153, 4, 99, 10, 0]).unwrap_err().kind
+        );
+
+        // Without the registration the opcode is unknown.
+        let mut machine = Intcode::new(vec![50, 0, 99]);
+        assert_eq!(
+            ErrorKind::UnknownOpcode(50),
+            machine.resume().unwrap_err().kind
+        );
+    }
+
+    #[test]
+    fn test_tracing() {
+        let mut machine = Intcode::new(vec![1150, 7, 99]);
+        machine.register_opcode(50, Arc::new(HostCall));
+        let mut events = Vec::new();
+        assert_eq!(
+            ErrorKind::TracedExtensions,
+            machine.step_traced(&mut events).unwrap_err().kind
+        );
+        assert!(events.is_empty());
+        assert_eq!(0, machine.executed());
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_builtin_opcode() {
+        Intcode::new(vec![99]).register_opcode(1, Arc::new(HostCall));
+    }
+}
@@ -0,0 +1,252 @@
+//! Differential fuzzing of the interpreter against the `reference` one.
+//!
+//! `Case::generate` builds a random program from a seed: mostly well-formed instructions in
+//! all the parameter modes, pointing into the program or its data, with some large values,
+//! far addresses and invalid instructions thrown in. `check` runs it on both interpreters one
+//! step at a time and reports the first difference in the results, the registers or the
+//! memory. The cases are reproducible from their seed alone.
+
+use super::reference::Reference;
+use super::{Byte, Intcode, State};
+use std::fmt;
+
+/// A xorshift64* generator: small, fast and good enough to pick instructions.
+#[derive(Debug, Clone)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        // The state must not be zero.
+        Rng {
+            state: seed ^ 0x9e37_79b9_7f4a_7c15,
+        }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        self.state.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    /// A number in `0..n`.
+    pub fn below(&mut self, n: usize) -> usize {
+        (self.next_u64() % n as u64) as usize
+    }
+
+    /// Returns `true` with the probability `1 / n`.
+    pub fn one_in(&mut self, n: usize) -> bool {
+        self.below(n) == 0
+    }
+}
+
+/// A generated program with the settings it runs with.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Case {
+    pub seed: u64,
+    pub code: Vec<Byte>,
+    pub inputs: Vec<Byte>,
+    pub max_address: usize,
+    pub checked_arithmetic: bool,
+}
+
+const MAX_ADDRESS: usize = 4095;
+
+// The opcodes with their arity and the index of the written operand.
+const INSTRUCTIONS: [(Byte, usize, Option<usize>); 10] = [
+    (1, 3, Some(3)),
+    (2, 3, Some(3)),
+    (3, 1, Some(1)),
+    (4, 1, None),
+    (5, 2, None),
+    (6, 2, None),
+    (7, 3, Some(3)),
+    (8, 3, Some(3)),
+    (9, 1, None),
+    (99, 0, None),
+];
+
+fn value(rng: &mut Rng) -> Byte {
+    match rng.below(12) {
+        0 => [Byte::MIN, Byte::MAX, -1, 1 << 40, -(1 << 40)][rng.below(5)],
+        1 => rng.next_u64() as i64 as Byte,
+        _ => rng.below(41) as Byte - 20,
+    }
+}
+
+impl Case {
+    pub fn generate(seed: u64) -> Self {
+        let mut rng = Rng::new(seed);
+        let instructions = 5 + rng.below(40);
+        let data = 5 + rng.below(30);
+        // The instructions are 1 to 4 cells long: the data starts at a guessed address, which
+        // is good enough since all the addresses are random anyway.
+        let size = instructions * 3 + data;
+        let mut code = Vec::new();
+        let mut starts = Vec::new();
+        // The immediate jump targets, pointed to the starts of the instructions at the end.
+        let mut targets = Vec::new();
+        for _ in 0..instructions {
+            starts.push(code.len());
+            let (opcode, arity, written) = if rng.one_in(20) {
+                INSTRUCTIONS[9]
+            } else {
+                INSTRUCTIONS[rng.below(9)]
+            };
+            let mut instruction = opcode;
+            let mut parameters = Vec::new();
+            let mut scale = 100;
+            for operand in 1..=arity {
+                let mut mode = if Some(operand) == written {
+                    [0, 2][rng.below(2)]
+                } else {
+                    rng.below(3) as Byte
+                };
+                if rng.one_in(50) {
+                    // An invalid mode or a write in the immediate mode.
+                    mode = [1, 3, 9][rng.below(3)];
+                }
+                instruction += mode * scale;
+                scale *= 10;
+                let jump_target = (opcode == 5 || opcode == 6) && operand == 2;
+                if mode == 1 && jump_target && !rng.one_in(10) {
+                    targets.push(code.len() + 1 + parameters.len());
+                }
+                parameters.push(match mode {
+                    1 if opcode == 9 => rng.below(14) as Byte - 3,
+                    1 => value(&mut rng),
+                    2 if rng.one_in(10) => -(rng.below(10) as Byte),
+                    2 => rng.below(size) as Byte,
+                    _ if rng.one_in(30) => (MAX_ADDRESS - 20 + rng.below(40)) as Byte,
+                    _ => rng.below(size) as Byte,
+                });
+            }
+            if rng.one_in(100) {
+                instruction = value(&mut rng);
+            }
+            code.push(instruction);
+            code.extend(parameters);
+        }
+        for &target in targets.iter() {
+            code[target] = starts[rng.below(starts.len())] as Byte;
+        }
+        for _ in 0..data {
+            code.push(if rng.one_in(2) {
+                starts[rng.below(starts.len())] as Byte
+            } else {
+                value(&mut rng)
+            });
+        }
+        let inputs = (0..rng.below(10)).map(|_| value(&mut rng)).collect();
+        Case {
+            seed,
+            code,
+            inputs,
+            max_address: MAX_ADDRESS,
+            checked_arithmetic: rng.one_in(2),
+        }
+    }
+}
+
+/// The first difference between the interpreters.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Divergence {
+    pub seed: u64,
+    /// The number of steps both interpreters agreed on.
+    pub step: usize,
+    pub message: String,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "seed {}, step {}: {}",
+            self.seed, self.step, self.message
+        )
+    }
+}
+
+/// Runs the case on both interpreters for at most `fuel` steps.
+pub fn check(case: &Case, fuel: usize) -> Result<(), Divergence> {
+    let mut machine = Intcode::new(case.code.to_vec());
+    machine.set_max_address(case.max_address);
+    machine.set_checked_arithmetic(case.checked_arithmetic);
+    let mut reference = Reference::new(case.code.to_vec());
+    reference.max_address = case.max_address;
+    reference.checked_arithmetic = case.checked_arithmetic;
+    let mut inputs = case.inputs.iter();
+    let diverged = |step: usize, message: String| {
+        Err(Divergence {
+            seed: case.seed,
+            step,
+            message,
+        })
+    };
+    for step in 0..fuel {
+        let result = machine.step();
+        let expected = reference.step();
+        match (&result, &expected) {
+            (Ok(state), Ok(expected)) if state == expected => {}
+            (Err(err), Err(kind)) if err.kind == *kind && err.ip == reference.ip => {}
+            _ => {
+                return diverged(
+                    step,
+                    format!("got {:?}, the reference got {:?}", result, expected),
+                )
+            }
+        }
+        if (machine.ip(), machine.relative_base(), machine.executed())
+            != (reference.ip, reference.relative_base, reference.executed)
+        {
+            return diverged(
+                step,
+                format!(
+                    "ip, relative base, executed: got {:?}, the reference got {:?}",
+                    (machine.ip(), machine.relative_base(), machine.executed()),
+                    (reference.ip, reference.relative_base, reference.executed)
+                ),
+            );
+        }
+        if machine.memory().as_slice() != &reference.memory[..] {
+            return diverged(step, "the memory differs".to_string());
+        }
+        match result {
+            Ok(Some(State::NeedsInput)) => match inputs.next() {
+                Some(&value) => {
+                    machine.push_input(value);
+                    reference.inputs.push_back(value);
+                }
+                None => return Ok(()),
+            },
+            Ok(Some(State::Halted)) | Err(_) => return Ok(()),
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Checks `count` cases with the seeds from `first_seed` on.
+pub fn fuzz(first_seed: u64, count: u64, fuel: usize) -> Result<(), Divergence> {
+    (first_seed..first_seed + count).try_for_each(|seed| check(&Case::generate(seed), fuel))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_generate() {
+        assert_eq!(Case::generate(42), Case::generate(42));
+        assert_ne!(Case::generate(42).code, Case::generate(43).code);
+    }
+
+    #[test]
+    fn test_fuzz() {
+        if let Err(divergence) = fuzz(0, 2000, 1000) {
+            panic!("{}\n{:?}", divergence, Case::generate(divergence.seed));
+        }
+    }
+}
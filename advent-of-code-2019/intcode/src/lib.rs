@@ -1,13 +1,55 @@
+use extension::{Context, Effect, Extension};
+use memory::{DenseMemory, Memory};
+use snapshot::Snapshot;
+use std::collections::{HashMap, VecDeque};
+use std::convert::TryFrom;
+use std::error;
+use std::fmt;
 use std::io;
+use std::sync::Arc;
+use trace::{Event, Tracer};
 
+pub mod ascii;
+pub mod asm;
+pub mod cfg;
+pub mod circuit;
+#[cfg(test)]
+mod conformance;
+pub mod disasm;
+pub mod extension;
+pub mod fuzz;
+pub mod memory;
+pub mod network;
+pub mod reference;
+pub mod scheduler;
+pub mod search;
+pub mod session;
+pub mod snapshot;
+pub mod trace;
+pub mod transpile;
+
+// The code generated by the transpiler refers to the crate by its name.
+#[cfg(test)]
+extern crate self as intcode;
+
+#[cfg(not(feature = "wide"))]
 pub type Byte = i64;
+/// The wide word type for the programs that need values beyond the 64-bit range.
+#[cfg(feature = "wide")]
+pub type Byte = i128;
 
 pub trait Io {
     fn input(&mut self) -> Byte;
     fn output(&mut self, value: Byte);
+
+    /// Like `input`, but returns `None` when there is no more input (e.g. at the end of a file).
+    fn try_input(&mut self) -> Option<Byte> {
+        Some(self.input())
+    }
 }
 
-enum Opcode {
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Opcode {
     Add,
     Multiply,
     Input,
@@ -21,25 +63,94 @@ enum Opcode {
 }
 
 impl Opcode {
-    fn from_code(code: Byte) -> Opcode {
+    pub fn from_code(code: Byte) -> Option<Opcode> {
         match code {
-            1 => Opcode::Add,
-            2 => Opcode::Multiply,
-            3 => Opcode::Input,
-            4 => Opcode::Output,
-            5 => Opcode::JumpIfTrue,
-            6 => Opcode::JumpIfFalse,
-            7 => Opcode::LessThan,
-            8 => Opcode::Equals,
-            9 => Opcode::UpdateRelativeBase,
-            99 => Opcode::Stop,
-            _ => panic!("Unknown opcode: {}", code),
+            1 => Some(Opcode::Add),
+            2 => Some(Opcode::Multiply),
+            3 => Some(Opcode::Input),
+            4 => Some(Opcode::Output),
+            5 => Some(Opcode::JumpIfTrue),
+            6 => Some(Opcode::JumpIfFalse),
+            7 => Some(Opcode::LessThan),
+            8 => Some(Opcode::Equals),
+            9 => Some(Opcode::UpdateRelativeBase),
+            99 => Some(Opcode::Stop),
+            _ => None,
+        }
+    }
+
+    pub fn code(self) -> Byte {
+        match self {
+            Opcode::Add => 1,
+            Opcode::Multiply => 2,
+            Opcode::Input => 3,
+            Opcode::Output => 4,
+            Opcode::JumpIfTrue => 5,
+            Opcode::JumpIfFalse => 6,
+            Opcode::LessThan => 7,
+            Opcode::Equals => 8,
+            Opcode::UpdateRelativeBase => 9,
+            Opcode::Stop => 99,
+        }
+    }
+
+    /// The number of operands that follow the instruction.
+    pub fn arity(self) -> usize {
+        match self {
+            Opcode::Add | Opcode::Multiply | Opcode::LessThan | Opcode::Equals => 3,
+            Opcode::JumpIfTrue | Opcode::JumpIfFalse => 2,
+            Opcode::Input | Opcode::Output | Opcode::UpdateRelativeBase => 1,
+            Opcode::Stop => 0,
+        }
+    }
+
+    /// The index (1-based) of the operand the instruction writes to, if any.
+    pub fn output_operand(self) -> Option<usize> {
+        match self {
+            Opcode::Add | Opcode::Multiply | Opcode::LessThan | Opcode::Equals => Some(3),
+            Opcode::Input => Some(1),
+            _ => None,
+        }
+    }
+
+    pub fn mnemonic(self) -> &'static str {
+        match self {
+            Opcode::Add => "add",
+            Opcode::Multiply => "mul",
+            Opcode::Input => "in",
+            Opcode::Output => "out",
+            Opcode::JumpIfTrue => "jt",
+            Opcode::JumpIfFalse => "jf",
+            Opcode::LessThan => "lt",
+            Opcode::Equals => "eq",
+            Opcode::UpdateRelativeBase => "arb",
+            Opcode::Stop => "hlt",
         }
     }
+
+    pub fn from_mnemonic(mnemonic: &str) -> Option<Opcode> {
+        ALL_OPCODES
+            .iter()
+            .copied()
+            .find(|opcode| opcode.mnemonic() == mnemonic)
+    }
 }
 
+pub const ALL_OPCODES: [Opcode; 10] = [
+    Opcode::Add,
+    Opcode::Multiply,
+    Opcode::Input,
+    Opcode::Output,
+    Opcode::JumpIfTrue,
+    Opcode::JumpIfFalse,
+    Opcode::LessThan,
+    Opcode::Equals,
+    Opcode::UpdateRelativeBase,
+    Opcode::Stop,
+];
+
 // Parameter mode
-#[derive(PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 enum Mode {
     Position,
     Immediate,
@@ -48,226 +159,665 @@ enum Mode {
 
 struct Modes {
     value: Byte,
+    operand: usize,
 }
 
 impl Modes {
     fn new(value: Byte) -> Self {
-        Modes { value }
+        Modes { value, operand: 0 }
     }
 
-    fn next(&mut self) -> Mode {
+    // Returns the mode of the next operand together with its (1-based) index.
+    fn next(&mut self) -> Result<(usize, Mode), ErrorKind> {
         let code = self.value % 10;
         self.value /= 10;
-        match code {
+        self.operand += 1;
+        let mode = match code {
             0 => Mode::Position,
             1 => Mode::Immediate,
             2 => Mode::Relative,
-            _ => panic!("Unknown mode: {}", code),
+            _ => {
+                return Err(ErrorKind::InvalidMode {
+                    operand: self.operand,
+                    mode: code,
+                })
+            }
+        };
+        Ok((self.operand, mode))
+    }
+}
+
+fn parse_instruction(instruction: Byte) -> Result<(Opcode, Modes), ErrorKind> {
+    let code = instruction % 100;
+    match Opcode::from_code(code) {
+        Some(opcode) => Ok((opcode, Modes::new(instruction / 100))),
+        None => Err(ErrorKind::UnknownOpcode(code)),
+    }
+}
+
+// An instruction with the modes of all its operands decoded and validated.
+#[derive(Debug, Clone, Copy)]
+struct Decoded {
+    opcode: Opcode,
+    modes: [Mode; 3],
+}
+
+impl Decoded {
+    fn new(instruction: Byte) -> Result<Self, ErrorKind> {
+        let (opcode, mut modes) = parse_instruction(instruction)?;
+        let mut decoded = Decoded {
+            opcode,
+            modes: [Mode::Position; 3],
+        };
+        for mode in decoded.modes.iter_mut().take(opcode.arity()) {
+            *mode = modes.next()?.1;
+        }
+        Ok(decoded)
+    }
+}
+
+// The cursor over the operands of the executed instruction.
+struct Operands {
+    modes: [Mode; 3],
+    operand: usize,
+}
+
+impl Operands {
+    // Returns the mode of the next operand together with its (1-based) index.
+    fn next(&mut self) -> (usize, Mode) {
+        let mode = self.modes[self.operand];
+        self.operand += 1;
+        (self.operand, mode)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ErrorKind {
+    UnknownOpcode(Byte),
+    InvalidMode {
+        operand: usize,
+        mode: Byte,
+    },
+    /// An operand resolved to a negative memory address (or a negative jump target).
+    NegativeAddress {
+        operand: usize,
+        address: Byte,
+    },
+    /// An operand resolved to an address above the maximum address of the machine (the operand
+    /// is 0 for the addresses given from outside of the program or by an extension).
+    AddressOutOfRange {
+        operand: usize,
+        address: Byte,
+    },
+    WriteInImmediateMode {
+        operand: usize,
+    },
+    /// The program asked for an input, but none was provided.
+    InputExhausted,
+    /// An arithmetic operation overflowed (only reported with the checked arithmetic).
+    Overflow,
+    /// The program produced an output, but it was run without IO.
+    UnexpectedOutput(Byte),
+    /// The machine with extensions was traced (see `Intcode::step_traced`).
+    TracedExtensions,
+}
+
+/// A failure of the program together with the state of the machine at the failing instruction.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RuntimeError {
+    pub kind: ErrorKind,
+    pub ip: usize,
+    pub instruction: Byte,
+    pub relative_base: Byte,
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ErrorKind::UnknownOpcode(code) => write!(f, "unknown opcode {}", code),
+            ErrorKind::InvalidMode { operand, mode } => {
+                write!(f, "invalid mode {} of operand {}", mode, operand)
+            }
+            ErrorKind::NegativeAddress { operand, address } => {
+                write!(f, "negative address {} in operand {}", address, operand)
+            }
+            ErrorKind::AddressOutOfRange { operand, address } => {
+                write!(f, "address {} out of range in operand {}", address, operand)
+            }
+            ErrorKind::WriteInImmediateMode { operand } => {
+                write!(f, "write to operand {} in immediate mode", operand)
+            }
+            ErrorKind::Overflow => write!(f, "arithmetic overflow"),
+            ErrorKind::InputExhausted => write!(f, "no input left"),
+            ErrorKind::UnexpectedOutput(value) => write!(f, "unexpected output {}", value),
+            ErrorKind::TracedExtensions => {
+                write!(f, "the machines with extensions can't be traced")
+            }
         }
     }
 }
 
-fn parse_instruction(instruction: Byte) -> (Opcode, Modes) {
-    (
-        Opcode::from_code(instruction % 100),
-        Modes::new(instruction / 100),
-    )
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} at ip {} (instruction {}, relative base {})",
+            self.kind, self.ip, self.instruction, self.relative_base
+        )
+    }
+}
+
+impl error::Error for RuntimeError {}
+
+/// The reason why a resumed machine gave control back to the caller.
+#[derive(Debug, PartialEq)]
+pub enum State {
+    /// The machine is blocked on an input instruction: provide a value with `push_input`.
+    NeedsInput,
+    Output(Byte),
+    Halted,
 }
 
-type RuntimeError = &'static str;
+/// The default limit of addresses the programs can access.
+pub const DEFAULT_MAX_ADDRESS: usize = (1 << 24) - 1;
 
-pub struct Intcode {
-    code: Vec<Byte>,
+// The instructions are cached only below this address (and below the length of the memory), so
+// that the far jumps in a sparse memory don't allocate a huge cache.
+const MAX_CACHED_ADDRESS: usize = 1 << 16;
+
+#[derive(Clone)]
+pub struct Intcode<M: Memory = DenseMemory> {
+    memory: M,
+    max_address: usize,
+    checked_arithmetic: bool,
+    ip: usize,
     relative_base: Byte,
+    input: VecDeque<Byte>,
+    executed: u64,
+    // The decoded instructions by their address (see `set_instruction_cache`).
+    cache: Option<Vec<Option<Decoded>>>,
+    extensions: HashMap<Byte, Arc<dyn Extension>>,
+    // The record of the last executed instruction.
+    event: Event,
 }
 
 impl Intcode {
-    fn new(code: Vec<Byte>) -> Self {
+    pub fn new(code: Vec<Byte>) -> Self {
+        Intcode::with_memory(DenseMemory::from_vec(code))
+    }
+}
+
+impl<M: Memory> Intcode<M> {
+    pub fn with_memory(memory: M) -> Self {
         Intcode {
-            code,
+            memory,
+            max_address: DEFAULT_MAX_ADDRESS,
+            checked_arithmetic: false,
+            ip: 0,
             relative_base: 0,
+            input: VecDeque::new(),
+            executed: 0,
+            cache: Some(Vec::new()),
+            extensions: HashMap::new(),
+            event: Event::new(0, Opcode::Stop),
         }
     }
 
-    fn ensure_memory(&mut self, i: usize) {
-        let min_len = i + 1;
-        if min_len > self.code.len() {
-            println!(
-                "Resizing the memory from {} to {}",
-                self.code.len(),
-                min_len
-            );
-            self.code.resize(min_len, 0);
-        }
+    /// Sets the highest address the program can access: the access to any address above
+    /// it fails with `ErrorKind::AddressOutOfRange`.
+    pub fn set_max_address(&mut self, max_address: usize) {
+        self.max_address = max_address;
     }
 
-    fn is_valid_index(&self, i: Byte) -> bool {
-        0 <= i
+    /// With the checked arithmetic an overflow of additions, multiplications and relative
+    /// base updates fails with `ErrorKind::Overflow`. Otherwise the values wrap around.
+    pub fn set_checked_arithmetic(&mut self, checked: bool) {
+        self.checked_arithmetic = checked;
     }
 
-    fn get_pos(&mut self, ip: usize, mode: Mode) -> Result<usize, RuntimeError> {
-        match mode {
-            Mode::Immediate => {
-                self.ensure_memory(ip);
-                Ok(ip)
+    pub fn ip(&self) -> usize {
+        self.ip
+    }
+
+    pub fn relative_base(&self) -> Byte {
+        self.relative_base
+    }
+
+    /// The number of instructions executed so far (a halt or a blocked input doesn't count).
+    pub fn executed(&self) -> u64 {
+        self.executed
+    }
+
+    pub fn memory(&self) -> &M {
+        &self.memory
+    }
+
+    pub fn into_memory(self) -> M {
+        self.memory
+    }
+
+    /// Captures the full state of the machine (including the queued inputs).
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            memory: self.memory.segments(),
+            ip: self.ip,
+            relative_base: self.relative_base,
+            input: self.input.iter().copied().collect(),
+            executed: self.executed,
+        }
+    }
+
+    /// Puts the machine back in the captured state. The settings of the machine (the maximum
+    /// address, the checked arithmetic, the instruction cache and the extensions) are kept.
+    /// Fails with `ErrorKind::AddressOutOfRange`, leaving the machine as it was, if the memory
+    /// of the snapshot goes above the maximum address.
+    pub fn restore(&mut self, snapshot: Snapshot) -> Result<(), RuntimeError> {
+        for (start, values) in snapshot.memory.iter() {
+            if !values.is_empty() {
+                let last = start.saturating_add(values.len() - 1);
+                check_address(last, self.max_address).map_err(|kind| self.error(kind))?;
             }
-            Mode::Position | Mode::Relative => {
-                self.ensure_memory(ip);
-                let mut pos = self.code[ip];
-                if mode == Mode::Relative {
-                    pos += self.relative_base;
-                }
-                if !self.is_valid_index(pos) {
-                    Err("Index out of bound")
-                } else {
-                    Ok(pos as usize)
+        }
+        self.memory = M::from_segments(snapshot.memory);
+        self.ip = snapshot.ip;
+        self.relative_base = snapshot.relative_base;
+        self.input = snapshot.input.into();
+        self.executed = snapshot.executed;
+        if let Some(cache) = self.cache.as_mut() {
+            cache.clear();
+        }
+        Ok(())
+    }
+
+    /// Enables (the default) or disables the cache of the decoded instructions. The cached
+    /// instructions are invalidated by the writes to their first cell, so the self-modifying
+    /// programs work the same with the cache.
+    pub fn set_instruction_cache(&mut self, enabled: bool) {
+        self.cache = if enabled { Some(Vec::new()) } else { None };
+    }
+
+    /// Registers the handler of the instructions with the opcode (the two lowest digits).
+    ///
+    /// Panics if the opcode is not in `1..100` or is the opcode of a built-in instruction.
+    pub fn register_opcode(&mut self, opcode: Byte, extension: Arc<dyn Extension>) {
+        assert!(
+            (1..100).contains(&opcode) && Opcode::from_code(opcode).is_none(),
+            "opcode {} can't be registered",
+            opcode
+        );
+        self.extensions.insert(opcode, extension);
+    }
+
+    /// Overwrites a memory cell. Fails with `ErrorKind::AddressOutOfRange` above the maximum
+    /// address, as the writes of the program do.
+    pub fn write_memory(&mut self, address: usize, value: Byte) -> Result<(), RuntimeError> {
+        check_address(address, self.max_address).map_err(|kind| self.error(kind))?;
+        self.invalidate(address);
+        self.memory.write(address, value);
+        Ok(())
+    }
+
+    #[inline]
+    fn invalidate(&mut self, address: usize) {
+        if let Some(entry) = self.cache.as_mut().and_then(|cache| cache.get_mut(address)) {
+            *entry = None;
+        }
+    }
+
+    #[inline]
+    fn decode(&mut self) -> Result<Decoded, RuntimeError> {
+        let ip = self.ip;
+        if let Some(Some(decoded)) = self.cache.as_ref().and_then(|cache| cache.get(ip)) {
+            return Ok(*decoded);
+        }
+        let decoded = Decoded::new(self.memory.read(ip)).map_err(|kind| self.error(kind))?;
+        if let Some(cache) = self.cache.as_mut() {
+            if ip < self.memory.len().min(MAX_CACHED_ADDRESS) {
+                if ip >= cache.len() {
+                    cache.resize(ip + 1, None);
                 }
+                cache[ip] = Some(decoded);
             }
         }
+        Ok(decoded)
+    }
+
+    /// Queues a value to be consumed by the next input instruction(s).
+    pub fn push_input(&mut self, value: Byte) {
+        self.input.push_back(value);
+    }
+
+    fn error_at(&self, ip: usize, kind: ErrorKind) -> RuntimeError {
+        RuntimeError {
+            kind,
+            ip,
+            instruction: self.memory.read(ip),
+            relative_base: self.relative_base,
+        }
+    }
+
+    fn error(&self, kind: ErrorKind) -> RuntimeError {
+        self.error_at(self.ip, kind)
+    }
+
+    fn to_address(&self, operand: usize, address: Byte) -> Result<usize, RuntimeError> {
+        if address < 0 {
+            Err(self.error(ErrorKind::NegativeAddress { operand, address }))
+        } else if address as u128 > self.max_address as u128 {
+            Err(self.error(ErrorKind::AddressOutOfRange { operand, address }))
+        } else {
+            Ok(address as usize)
+        }
+    }
+
+    fn get_pos(&self, operand: usize, mode: Mode) -> Result<usize, RuntimeError> {
+        let ip = self.ip + operand;
+        match mode {
+            Mode::Immediate => Ok(ip),
+            Mode::Position => self.to_address(operand, self.memory.read(ip)),
+            Mode::Relative => match self.memory.read(ip).checked_add(self.relative_base) {
+                Some(address) => self.to_address(operand, address),
+                None => Err(self.error(ErrorKind::Overflow)),
+            },
+        }
+    }
+
+    fn arithmetic<Checked, Wrapping>(
+        &self,
+        a: Byte,
+        b: Byte,
+        checked: Checked,
+        wrapping: Wrapping,
+    ) -> Result<Byte, RuntimeError>
+    where
+        Checked: Fn(Byte, Byte) -> Option<Byte>,
+        Wrapping: Fn(Byte, Byte) -> Byte,
+    {
+        if self.checked_arithmetic {
+            checked(a, b).ok_or_else(|| self.error(ErrorKind::Overflow))
+        } else {
+            Ok(wrapping(a, b))
+        }
     }
 
-    fn read_ref(&mut self, ip: usize, mode: Mode) -> Result<Byte, RuntimeError> {
-        let pos = self.get_pos(ip, mode)?;
-        self.ensure_memory(pos);
-        Ok(self.code[pos])
+    fn read(&mut self, modes: &mut Operands) -> Result<Byte, RuntimeError> {
+        let (operand, mode) = modes.next();
+        let pos = self.get_pos(operand, mode)?;
+        let value = self.memory.read(pos);
+        self.event.push_operand(value);
+        Ok(value)
     }
 
-    fn write_ref(&mut self, ip: usize, mode: Mode, value: Byte) -> Result<(), RuntimeError> {
-        let pos = self.get_pos(ip, mode)?;
-        self.ensure_memory(pos);
-        self.code[pos] = value;
+    fn write(&mut self, modes: &mut Operands, value: Byte) -> Result<(), RuntimeError> {
+        let (operand, mode) = modes.next();
+        if mode == Mode::Immediate {
+            return Err(self.error(ErrorKind::WriteInImmediateMode { operand }));
+        }
+        let pos = self.get_pos(operand, mode)?;
+        self.invalidate(pos);
+        self.memory.write(pos, value);
+        self.event.written = Some((pos, value));
         Ok(())
     }
 
-    pub fn run(mut self, io: &mut dyn Io) -> Result<Vec<Byte>, RuntimeError> {
-        let mut i: usize = 0;
-        loop {
-            self.ensure_memory(i);
-            let (opcode, mut modes) = parse_instruction(self.code[i]);
-            match opcode {
-                Opcode::Add => {
-                    let val0 = self.read_ref(i + 1, modes.next())?;
-                    let val1 = self.read_ref(i + 2, modes.next())?;
-                    self.write_ref(i + 3, modes.next(), val0 + val1)?;
-                    i += 4;
-                }
-                Opcode::Multiply => {
-                    let val0 = self.read_ref(i + 1, modes.next())?;
-                    let val1 = self.read_ref(i + 2, modes.next())?;
-                    self.write_ref(i + 3, modes.next(), val0 * val1)?;
-                    i += 4;
-                }
-                Opcode::Input => {
-                    let input = io.input();
-                    self.write_ref(i + 1, modes.next(), input)?;
-                    i += 2;
-                }
-                Opcode::Output => {
-                    let val0 = self.read_ref(i + 1, modes.next())?;
-                    io.output(val0);
-                    i += 2;
-                }
-                Opcode::JumpIfTrue => {
-                    let val0 = self.read_ref(i + 1, modes.next())?;
-                    if val0 != 0 {
-                        let ip = self.read_ref(i + 2, modes.next())?;
-                        if !self.is_valid_index(ip) {
-                            return Err("Jump out of boundaries");
-                        }
-                        i = ip as usize;
-                        continue;
+    /// Executes a single instruction. Returns the state if the machine gives control back
+    /// to the caller (see `resume`), `None` if it can continue.
+    pub fn step(&mut self) -> Result<Option<State>, RuntimeError> {
+        let Decoded { opcode, modes } = match self.decode() {
+            Ok(decoded) => decoded,
+            Err(err) => {
+                return match err.kind {
+                    ErrorKind::UnknownOpcode(code) if self.extensions.contains_key(&code) => {
+                        self.step_extension(code)
                     }
-                    i += 3;
-                }
-                Opcode::JumpIfFalse => {
-                    let val0 = self.read_ref(i + 1, modes.next())?;
-                    if val0 == 0 {
-                        let ip = self.read_ref(i + 2, modes.next())?;
-                        if !self.is_valid_index(ip) {
-                            return Err("Jump out of boundaries");
-                        }
-                        i = ip as usize;
-                        continue;
-                    }
-                    i += 3;
-                }
-                Opcode::LessThan => {
-                    let val0 = self.read_ref(i + 1, modes.next())?;
-                    let val1 = self.read_ref(i + 2, modes.next())?;
-                    let res = if val0 < val1 { 1 } else { 0 };
-                    self.write_ref(i + 3, modes.next(), res)?;
-                    i += 4;
-                }
-                Opcode::Equals => {
-                    let val0 = self.read_ref(i + 1, modes.next())?;
-                    let val1 = self.read_ref(i + 2, modes.next())?;
-                    let res = if val0 == val1 { 1 } else { 0 };
-                    self.write_ref(i + 3, modes.next(), res)?;
-                    i += 4;
+                    _ => Err(err),
                 }
-                Opcode::UpdateRelativeBase => {
-                    let val0 = self.read_ref(i + 1, modes.next())?;
-                    self.relative_base += val0;
-                    i += 2;
-                }
-                Opcode::Stop => {
-                    return Ok(self.code);
+            }
+        };
+        let mut modes = Operands { modes, operand: 0 };
+        self.event = Event::new(self.ip, opcode);
+        match opcode {
+            Opcode::Add => {
+                let val0 = self.read(&mut modes)?;
+                let val1 = self.read(&mut modes)?;
+                let res = self.arithmetic(val0, val1, Byte::checked_add, Byte::wrapping_add)?;
+                self.write(&mut modes, res)?;
+                self.ip += 4;
+            }
+            Opcode::Multiply => {
+                let val0 = self.read(&mut modes)?;
+                let val1 = self.read(&mut modes)?;
+                let res = self.arithmetic(val0, val1, Byte::checked_mul, Byte::wrapping_mul)?;
+                self.write(&mut modes, res)?;
+                self.ip += 4;
+            }
+            Opcode::Input => {
+                let input = match self.input.pop_front() {
+                    Some(input) => input,
+                    None => return Ok(Some(State::NeedsInput)),
+                };
+                self.write(&mut modes, input)?;
+                self.ip += 2;
+            }
+            Opcode::Output => {
+                let val0 = self.read(&mut modes)?;
+                self.ip += 2;
+                self.executed += 1;
+                return Ok(Some(State::Output(val0)));
+            }
+            Opcode::JumpIfTrue | Opcode::JumpIfFalse => {
+                let val0 = self.read(&mut modes)?;
+                let target = self.read(&mut modes)?;
+                let jump = match opcode {
+                    Opcode::JumpIfTrue => val0 != 0,
+                    _ => val0 == 0,
+                };
+                if jump {
+                    self.ip = self.to_address(2, target)?;
+                } else {
+                    self.ip += 3;
                 }
             }
+            Opcode::LessThan => {
+                let val0 = self.read(&mut modes)?;
+                let val1 = self.read(&mut modes)?;
+                let res = if val0 < val1 { 1 } else { 0 };
+                self.write(&mut modes, res)?;
+                self.ip += 4;
+            }
+            Opcode::Equals => {
+                let val0 = self.read(&mut modes)?;
+                let val1 = self.read(&mut modes)?;
+                let res = if val0 == val1 { 1 } else { 0 };
+                self.write(&mut modes, res)?;
+                self.ip += 4;
+            }
+            Opcode::UpdateRelativeBase => {
+                let val0 = self.read(&mut modes)?;
+                self.relative_base = self.arithmetic(
+                    self.relative_base,
+                    val0,
+                    Byte::checked_add,
+                    Byte::wrapping_add,
+                )?;
+                self.ip += 2;
+            }
+            Opcode::Stop => {
+                return Ok(Some(State::Halted));
+            }
         }
+        self.executed += 1;
+        Ok(None)
     }
-}
 
-pub fn run_code(code: Vec<Byte>, io: &mut dyn Io) -> Result<Vec<Byte>, RuntimeError> {
-    Intcode::new(code).run(io)
-}
+    // Executes the instruction of a registered extension.
+    fn step_extension(&mut self, code: Byte) -> Result<Option<State>, RuntimeError> {
+        let extension = self.extensions[&code].clone();
+        let mut modes = Modes::new(self.memory.read(self.ip) / 100);
+        let mut operands = Vec::with_capacity(extension.arity());
+        for _ in 0..extension.arity() {
+            let (operand, mode) = modes.next().map_err(|kind| self.error(kind))?;
+            if extension.output_operand() == Some(operand) {
+                if mode == Mode::Immediate {
+                    return Err(self.error(ErrorKind::WriteInImmediateMode { operand }));
+                }
+                operands.push(self.get_pos(operand, mode)? as Byte);
+            } else {
+                let pos = self.get_pos(operand, mode)?;
+                operands.push(self.memory.read(pos));
+            }
+        }
+        let mut context = Context::new(
+            self.ip,
+            &operands,
+            self.max_address,
+            &mut self.memory,
+            &mut self.cache,
+            &mut self.relative_base,
+            &mut self.input,
+        );
+        let effect = extension
+            .execute(&mut context)
+            .map_err(|kind| self.error(kind))?;
+        let next = self.ip + 1 + operands.len();
+        let state = match effect {
+            Effect::Next => None,
+            Effect::Jump(address) => {
+                check_address(address, self.max_address).map_err(|kind| self.error(kind))?;
+                self.ip = address;
+                self.executed += 1;
+                return Ok(None);
+            }
+            Effect::NeedsInput => return Ok(Some(State::NeedsInput)),
+            Effect::Output(value) => Some(State::Output(value)),
+            Effect::Halt => return Ok(Some(State::Halted)),
+        };
+        self.ip = next;
+        self.executed += 1;
+        Ok(state)
+    }
 
-struct NoIo {}
+    /// Runs the program until it needs an input that was not queued yet, produces an output
+    /// or halts. The machine can be resumed again after any of these events.
+    pub fn resume(&mut self) -> Result<State, RuntimeError> {
+        loop {
+            if let Some(state) = self.step()? {
+                return Ok(state);
+            }
+        }
+    }
 
-impl Io for NoIo {
-    fn input(&mut self) -> Byte {
-        panic!("unexpected input request")
+    /// Executes a single instruction like `step`, reporting it to the tracer.
+    ///
+    /// Fails with `ErrorKind::TracedExtensions` if extensions are registered, without executing
+    /// anything: their instructions can't be reported as events, and the traces would be
+    /// incomplete without them.
+    pub fn step_traced(&mut self, tracer: &mut dyn Tracer) -> Result<Option<State>, RuntimeError> {
+        if !self.extensions.is_empty() {
+            return Err(self.error(ErrorKind::TracedExtensions));
+        }
+        let state = self.step()?;
+        if state != Some(State::NeedsInput) {
+            tracer.trace(&self.event);
+        }
+        Ok(state)
     }
 
-    fn output(&mut self, _: Byte) {
-        panic!("unexpected output request");
+    /// Runs the program until it halts, and returns the final memory. Fails with
+    /// `ErrorKind::InputExhausted` if the `Io` has no more input (see `Io::try_input`).
+    pub fn run(mut self, io: &mut dyn Io) -> Result<M, RuntimeError> {
+        self.run_with(io, |machine| machine.step())?;
+        Ok(self.memory)
     }
-}
 
-pub fn run_code_without_io(code: Vec<Byte>) -> Result<Vec<Byte>, RuntimeError> {
-    let mut io = NoIo {};
-    run_code(code, &mut io)
-}
+    /// Runs the program like `run`, reporting every executed instruction to the tracer.
+    pub fn run_traced(
+        mut self,
+        io: &mut dyn Io,
+        tracer: &mut dyn Tracer,
+    ) -> Result<M, RuntimeError> {
+        self.run_with(io, |machine| machine.step_traced(tracer))?;
+        Ok(self.memory)
+    }
 
-struct SimpleIo {
-    input: Vec<Byte>,
-    output: Vec<Byte>,
-}
+    // Runs the program until it halts, executing the instructions with `step`.
+    pub(crate) fn run_with<Step>(
+        &mut self,
+        io: &mut dyn Io,
+        mut step: Step,
+    ) -> Result<(), RuntimeError>
+    where
+        Step: FnMut(&mut Self) -> Result<Option<State>, RuntimeError>,
+    {
+        loop {
+            match step(self)? {
+                Some(State::NeedsInput) => match io.try_input() {
+                    Some(value) => self.push_input(value),
+                    None => return Err(self.error(ErrorKind::InputExhausted)),
+                },
+                Some(State::Output(value)) => io.output(value),
+                Some(State::Halted) => return Ok(()),
+                None => {}
+            }
+        }
+    }
 
-impl SimpleIo {
-    fn new(input: Vec<Byte>) -> Self {
-        SimpleIo {
-            input,
-            output: Vec::new(),
+    // Runs the program with the queued inputs only, collecting its outputs.
+    fn run_with_queued_inputs(mut self) -> Result<Vec<Byte>, RuntimeError> {
+        let mut output = Vec::new();
+        loop {
+            match self.resume()? {
+                State::NeedsInput => return Err(self.error(ErrorKind::InputExhausted)),
+                State::Output(value) => output.push(value),
+                State::Halted => return Ok(output),
+            }
         }
     }
 }
 
-impl Io for SimpleIo {
-    fn input(&mut self) -> Byte {
-        self.input.pop().unwrap()
+// Checks an address that doesn't come from an operand (written from outside of the program or by
+// an extension).
+fn check_address(address: usize, max_address: usize) -> Result<(), ErrorKind> {
+    if address > max_address {
+        Err(ErrorKind::AddressOutOfRange {
+            operand: 0,
+            address: Byte::try_from(address).unwrap_or(Byte::MAX),
+        })
+    } else {
+        Ok(())
     }
+}
+
+pub fn run_code(code: Vec<Byte>, io: &mut dyn Io) -> Result<Vec<Byte>, RuntimeError> {
+    Intcode::new(code).run(io).map(DenseMemory::into_vec)
+}
 
-    fn output(&mut self, value: Byte) {
-        self.output.push(value);
+pub fn run_code_without_io(code: Vec<Byte>) -> Result<Vec<Byte>, RuntimeError> {
+    let mut machine = Intcode::new(code);
+    match machine.resume()? {
+        State::NeedsInput => Err(machine.error(ErrorKind::InputExhausted)),
+        // The output instruction (2 bytes long) was already executed.
+        State::Output(value) => {
+            Err(machine.error_at(machine.ip - 2, ErrorKind::UnexpectedOutput(value)))
+        }
+        State::Halted => Ok(machine.into_memory().into_vec()),
     }
 }
 
+/// Runs the program, feeding it `inputs` starting from the last one, and returns its outputs.
+///
+/// Panics with the runtime error if the program fails. The programs talking in text are
+/// better run with `ascii::run_ascii`, which takes the input in its natural order.
 pub fn run_code_with_inputs(code: Vec<Byte>, inputs: Vec<Byte>) -> Vec<Byte> {
-    let mut io = SimpleIo::new(inputs);
-    let ok = run_code(code, &mut io).is_ok();
-    assert!(ok);
-    io.output
+    let mut machine = Intcode::new(code);
+    inputs
+        .into_iter()
+        .rev()
+        .for_each(|input| machine.push_input(input));
+    match machine.run_with_queued_inputs() {
+        Ok(output) => output,
+        Err(err) => panic!("{}", err),
+    }
 }
 
 pub fn parse_code(input: &str) -> Vec<Byte> {
@@ -287,6 +837,7 @@ pub fn load_code() -> Vec<Byte> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use memory::PagedMemory;
 
     #[test]
     fn check_run_code() {
@@ -316,14 +867,14 @@ mod tests {
         );
     }
 
-    fn test_run(code: &Vec<Byte>, input: Vec<Byte>) -> Vec<Byte> {
+    fn test_run(code: &[Byte], input: Vec<Byte>) -> Vec<Byte> {
         run_code_with_inputs(code.to_vec(), input)
     }
 
     #[test]
     fn test_jump() {
         // The programs: ask for input, if input == 0, output 0; else output 1
-        let codes = vec![
+        let codes = [
             vec![3, 12, 6, 12, 15, 1, 13, 14, 13, 4, 13, 99, -1, 0, 1, 9], // position mode
             vec![3, 3, 1105, -1, 9, 1101, 0, 0, 12, 4, 12, 99, 1],         // position mode
         ];
@@ -336,7 +887,7 @@ mod tests {
     #[test]
     fn test_less_than() {
         // The programs: ask for input, if input is < 8, output 1; else output 0
-        let codes = vec![
+        let codes = [
             vec![3, 9, 7, 9, 10, 9, 4, 9, 99, -1, 8], // position mode
             vec![3, 3, 1107, -1, 8, 3, 4, 3, 99],     // immediate mode
         ];
@@ -349,7 +900,7 @@ mod tests {
     #[test]
     fn test_equal_to() {
         // The programs: ask for input, if input is == 8, output 1 else output 0
-        let codes = vec![
+        let codes = [
             vec![3, 9, 8, 9, 10, 9, 4, 9, 99, -1, 8], // position mode
             vec![3, 3, 1108, -1, 8, 3, 4, 3, 99],     // immediate mode
         ];
@@ -374,4 +925,212 @@ mod tests {
         ];
         assert_eq!(code, test_run(&code, Vec::new()));
     }
+
+    #[test]
+    fn test_resume() {
+        // The program: ask for input, if input is < 8, output 1; else output 0
+        let mut machine = Intcode::new(vec![3, 9, 7, 9, 10, 9, 4, 9, 99, -1, 8]);
+        assert_eq!(Ok(State::NeedsInput), machine.resume());
+        assert_eq!(Ok(State::NeedsInput), machine.resume());
+        machine.push_input(7);
+        assert_eq!(Ok(State::Output(1)), machine.resume());
+        assert_eq!(Ok(State::Halted), machine.resume());
+        assert_eq!(Ok(State::Halted), machine.resume());
+    }
+
+    #[test]
+    fn test_memory_limits() {
+        // The program: write 42 to a far address and output it back.
+        let code = vec![1101, 40, 2, 1_000_000_000_000, 4, 1_000_000_000_000, 99];
+        let err = run_code_without_io(code.to_vec()).unwrap_err();
+        assert_eq!(
+            ErrorKind::AddressOutOfRange {
+                operand: 3,
+                address: 1_000_000_000_000
+            },
+            err.kind
+        );
+
+        let mut machine = Intcode::with_memory(PagedMemory::from_vec(code));
+        machine.set_max_address(usize::MAX);
+        assert_eq!(Ok(State::Output(42)), machine.resume());
+        assert_eq!(Ok(State::Halted), machine.resume());
+        assert_eq!(2, machine.memory().page_count());
+
+        let mut machine = Intcode::new(vec![1101, 1, 1, 10, 99]);
+        machine.set_max_address(9);
+        assert_eq!(
+            ErrorKind::AddressOutOfRange {
+                operand: 3,
+                address: 10
+            },
+            machine.resume().unwrap_err().kind
+        );
+
+        // The writes from outside of the program are checked as well.
+        let mut machine = Intcode::new(vec![99]);
+        machine.set_max_address(9);
+        assert_eq!(Ok(()), machine.write_memory(9, 1));
+        assert_eq!(
+            ErrorKind::AddressOutOfRange {
+                operand: 0,
+                address: 1_000_000
+            },
+            machine.write_memory(1_000_000, 1).unwrap_err().kind
+        );
+        assert_eq!(10, machine.memory().len());
+    }
+
+    #[test]
+    fn test_overflow() {
+        // The program: multiply the two inputs and output the result.
+        let code = vec![3, 11, 3, 12, 2, 11, 12, 13, 4, 13, 99, 0, 0, 0];
+        let big = Byte::MAX / 2 + 1;
+        let run = |checked| {
+            let mut machine = Intcode::new(code.to_vec());
+            machine.set_checked_arithmetic(checked);
+            machine.push_input(big);
+            machine.push_input(2);
+            machine.resume()
+        };
+        assert_eq!(Ok(State::Output(Byte::MIN)), run(false));
+        let err = run(true).unwrap_err();
+        assert_eq!((ErrorKind::Overflow, 4), (err.kind, err.ip));
+
+        // The relative address overflows regardless of the mode.
+        let err = run_code_without_io(vec![109, Byte::MAX, 204, 1, 99]).unwrap_err();
+        assert_eq!((ErrorKind::Overflow, 2), (err.kind, err.ip));
+    }
+
+    #[cfg(feature = "wide")]
+    #[test]
+    fn test_wide_numbers_support() {
+        let code = vec![1102, 1 << 62, 1 << 62, 7, 4, 7, 99, 0];
+        assert_eq!(vec![1 << 124], test_run(&code, Vec::new()));
+    }
+
+    #[test]
+    fn test_instruction_cache() {
+        // The program: output [9], then add 100 to the first instruction (turning it into
+        // `out #9`, then into `out rb+9`, then into an invalid instruction) and loop.
+        let code = vec![4, 9, 1001, 0, 100, 0, 1105, 1, 0, 7];
+        for cache in [true, false] {
+            let mut machine = Intcode::new(code.to_vec());
+            machine.set_instruction_cache(cache);
+            let mut output = Vec::new();
+            let err = loop {
+                match machine.resume() {
+                    Ok(State::Output(value)) => output.push(value),
+                    Ok(state) => panic!("unexpected state {:?}", state),
+                    Err(err) => break err,
+                }
+            };
+            assert_eq!(vec![7, 9, 7], output);
+            assert_eq!(
+                ErrorKind::InvalidMode {
+                    operand: 1,
+                    mode: 3
+                },
+                err.kind
+            );
+
+            let mut machine = Intcode::new(vec![104, 1, 99]);
+            machine.set_instruction_cache(cache);
+            assert_eq!(Ok(State::Output(1)), machine.resume());
+            assert_eq!(Ok(State::Halted), machine.resume());
+            machine.write_memory(2, 104).unwrap();
+            machine.write_memory(3, 2).unwrap();
+            assert_eq!(Ok(State::Output(2)), machine.resume());
+        }
+
+        // The program: write `hlt` at 10^12 and jump there.
+        let far = 1_000_000_000_000;
+        let code = vec![1101, 0, 99, far, 1105, 1, far];
+        let mut machine = Intcode::with_memory(PagedMemory::from_vec(code));
+        machine.set_max_address(usize::MAX);
+        assert_eq!(Ok(State::Halted), machine.resume());
+        assert_eq!(far as usize, machine.ip());
+        assert!(machine.cache.as_ref().unwrap().len() <= 7);
+    }
+
+    #[test]
+    fn test_step() {
+        let mut machine = Intcode::new(vec![1101, 2, 3, 7, 104, 5, 99, 0]);
+        assert_eq!(Ok(None), machine.step());
+        assert_eq!((4, 5), (machine.ip(), machine.memory().read(7)));
+        assert_eq!(Ok(Some(State::Output(5))), machine.step());
+        assert_eq!(Ok(Some(State::Halted)), machine.step());
+        assert_eq!((6, 2), (machine.ip(), machine.executed()));
+    }
+
+    #[test]
+    fn test_resume_keeps_relative_base() {
+        let code = vec![
+            109, 1, 204, -1, 1001, 100, 1, 100, 1008, 100, 16, 101, 1006, 101, 0, 99,
+        ];
+        let mut machine = Intcode::new(code.to_vec());
+        let mut output = Vec::new();
+        while let Ok(State::Output(value)) = machine.resume() {
+            assert_eq!(output.len() as Byte + 1, machine.relative_base());
+            output.push(value);
+        }
+        assert_eq!(code, output);
+    }
+
+    #[test]
+    fn test_runtime_errors() {
+        let err = run_code_without_io(vec![1, 0, 0, 0, 42]).unwrap_err();
+        assert_eq!(ErrorKind::UnknownOpcode(42), err.kind);
+        assert_eq!((4, 42), (err.ip, err.instruction));
+
+        let err = run_code_without_io(vec![109, 5, 30001, 0, 0, 0, 99]).unwrap_err();
+        assert_eq!(
+            ErrorKind::InvalidMode {
+                operand: 3,
+                mode: 3
+            },
+            err.kind
+        );
+        assert_eq!((2, 30001, 5), (err.ip, err.instruction, err.relative_base));
+
+        let err = run_code_without_io(vec![109, -10, 2201, 0, 0, 0, 99]).unwrap_err();
+        assert_eq!(
+            ErrorKind::NegativeAddress {
+                operand: 1,
+                address: -10
+            },
+            err.kind
+        );
+
+        let err = run_code_without_io(vec![1105, 1, -1]).unwrap_err();
+        assert_eq!(
+            ErrorKind::NegativeAddress {
+                operand: 2,
+                address: -1
+            },
+            err.kind
+        );
+
+        let err = run_code_without_io(vec![11101, 1, 1, 0, 99]).unwrap_err();
+        assert_eq!(ErrorKind::WriteInImmediateMode { operand: 3 }, err.kind);
+    }
+
+    #[test]
+    fn test_io_errors() {
+        let err = run_code_without_io(vec![3, 0, 99]).unwrap_err();
+        assert_eq!(ErrorKind::InputExhausted, err.kind);
+        assert_eq!(0, err.ip);
+
+        let err = run_code_without_io(vec![1101, 1, 1, 5, 104, 0, 99]).unwrap_err();
+        assert_eq!(ErrorKind::UnexpectedOutput(2), err.kind);
+        assert_eq!((4, 104), (err.ip, err.instruction));
+        assert_eq!(
+            "unexpected output 2 at ip 4 (instruction 104, relative base 0)",
+            err.to_string()
+        );
+
+        let machine = Intcode::new(vec![3, 0, 3, 0, 99]);
+        let err = machine.run_with_queued_inputs().unwrap_err();
+        assert_eq!(ErrorKind::InputExhausted, err.kind);
+    }
 }
@@ -0,0 +1,194 @@
+//! Memory backends of the Intcode machine.
+//!
+//! The memory is conceptually infinite and zero-initialized: reads past the written cells
+//! return zero and don't allocate anything. `DenseMemory` keeps all the cells up to the highest
+//! written address in a vector (the fast path for the usual programs), `PagedMemory` allocates
+//! only the pages that were written to.
+
+use super::Byte;
+use std::collections::HashMap;
+
+pub trait Memory {
+    fn from_vec(code: Vec<Byte>) -> Self
+    where
+        Self: Sized;
+
+    fn read(&self, address: usize) -> Byte;
+
+    fn write(&mut self, address: usize, value: Byte);
+
+    /// One past the highest address that was loaded or written.
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The allocated runs of cells as `(start address, values)`, in the address order. They
+    /// cover all the written cells (and maybe some zeros around them), but not the unallocated
+    /// gaps, so they stay small for the sparse memories.
+    fn segments(&self) -> Vec<(usize, Vec<Byte>)>;
+
+    fn from_segments(segments: Vec<(usize, Vec<Byte>)>) -> Self
+    where
+        Self: Sized,
+    {
+        let mut memory = Self::from_vec(Vec::new());
+        for (start, values) in segments {
+            for (offset, value) in values.into_iter().enumerate() {
+                memory.write(start + offset, value);
+            }
+        }
+        memory
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DenseMemory {
+    cells: Vec<Byte>,
+}
+
+impl DenseMemory {
+    pub fn as_slice(&self) -> &[Byte] {
+        &self.cells
+    }
+
+    pub fn into_vec(self) -> Vec<Byte> {
+        self.cells
+    }
+}
+
+impl Memory for DenseMemory {
+    fn from_vec(code: Vec<Byte>) -> Self {
+        DenseMemory { cells: code }
+    }
+
+    #[inline]
+    fn read(&self, address: usize) -> Byte {
+        self.cells.get(address).copied().unwrap_or(0)
+    }
+
+    #[inline]
+    fn write(&mut self, address: usize, value: Byte) {
+        if address >= self.cells.len() {
+            self.cells.resize(address + 1, 0);
+        }
+        self.cells[address] = value;
+    }
+
+    fn len(&self) -> usize {
+        self.cells.len()
+    }
+
+    fn segments(&self) -> Vec<(usize, Vec<Byte>)> {
+        if self.cells.is_empty() {
+            Vec::new()
+        } else {
+            vec![(0, self.cells.to_vec())]
+        }
+    }
+}
+
+const PAGE_BITS: usize = 10;
+const PAGE_SIZE: usize = 1 << PAGE_BITS;
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PagedMemory {
+    pages: HashMap<usize, Box<[Byte; PAGE_SIZE]>>,
+    len: usize,
+}
+
+impl PagedMemory {
+    /// The number of allocated pages.
+    pub fn page_count(&self) -> usize {
+        self.pages.len()
+    }
+}
+
+impl Memory for PagedMemory {
+    fn from_vec(code: Vec<Byte>) -> Self {
+        let mut memory = PagedMemory::default();
+        for (address, value) in code.into_iter().enumerate() {
+            memory.write(address, value);
+        }
+        memory
+    }
+
+    fn read(&self, address: usize) -> Byte {
+        match self.pages.get(&(address >> PAGE_BITS)) {
+            Some(page) => page[address & (PAGE_SIZE - 1)],
+            None => 0,
+        }
+    }
+
+    fn write(&mut self, address: usize, value: Byte) {
+        let page = self
+            .pages
+            .entry(address >> PAGE_BITS)
+            .or_insert_with(|| Box::new([0; PAGE_SIZE]));
+        page[address & (PAGE_SIZE - 1)] = value;
+        // The length saturates at the last address, which can't be followed by another one.
+        self.len = self.len.max(address.saturating_add(1));
+    }
+
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    fn segments(&self) -> Vec<(usize, Vec<Byte>)> {
+        let mut pages: Vec<_> = self.pages.iter().collect();
+        pages.sort_unstable_by_key(|&(&page, _)| page);
+        pages
+            .into_iter()
+            .map(|(&page, cells)| {
+                let start = page << PAGE_BITS;
+                // The last page is cut at the length, so that it's restored as well.
+                let count = (self.len - start).min(PAGE_SIZE);
+                (start, cells[..count].to_vec())
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check_memory<M: Memory>() {
+        let mut memory = M::from_vec(vec![1, 2, 3]);
+        assert_eq!((3, 2, 0), (memory.len(), memory.read(1), memory.read(10)));
+        assert_eq!(3, memory.len());
+        memory.write(5, -1);
+        assert_eq!(vec![(0, vec![1, 2, 3, 0, 0, -1])], memory.segments());
+        memory.write(0, 7);
+        let memory = M::from_segments(memory.segments());
+        assert_eq!(6, memory.len());
+        assert_eq!(vec![(0, vec![7, 2, 3, 0, 0, -1])], memory.segments());
+        assert!(M::from_segments(Vec::new()).is_empty());
+    }
+
+    #[test]
+    fn test_dense_memory() {
+        check_memory::<DenseMemory>();
+    }
+
+    #[test]
+    fn test_paged_memory() {
+        check_memory::<PagedMemory>();
+
+        let mut memory = PagedMemory::default();
+        memory.write(1_000_000_000_000, 42);
+        assert_eq!(1, memory.page_count());
+        assert_eq!(42, memory.read(1_000_000_000_000));
+        assert_eq!(0, memory.read(1_000_000_000_001));
+        assert_eq!(1_000_000_000_001, memory.len());
+
+        // The page starts at the address, and is cut right after it.
+        let segments = memory.segments();
+        assert_eq!(vec![(1_000_000_000_000, vec![42])], segments);
+        assert_eq!(memory, PagedMemory::from_segments(segments));
+
+        memory.write(usize::MAX, 7);
+        assert_eq!((7, usize::MAX), (memory.read(usize::MAX), memory.len()));
+    }
+}
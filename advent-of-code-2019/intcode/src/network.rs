@@ -0,0 +1,373 @@
+//! A network of Intcode computers exchanging packets (day 23).
+//!
+//! Every computer is booted with its address as the first input, then it sends packets as
+//! three outputs (the destination address, X and Y) and reads the packets sent to it as two
+//! inputs (X and Y), or `-1` when there are none. The addresses above the computers can be
+//! taken by devices, like the NAT at 255, which get the packets sent to them and can send
+//! packets when the network is idle.
+//!
+//! Every packet is kept in the `Log` together with the time it was sent: the number of
+//! instructions executed by all the computers so far.
+
+use super::scheduler::{Inputs, Scheduler, Topology};
+use super::{Byte, Intcode};
+use std::collections::HashMap;
+
+pub const NAT_ADDRESS: Byte = 255;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Packet {
+    pub from: Byte,
+    pub to: Byte,
+    pub x: Byte,
+    pub y: Byte,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LogEntry {
+    pub time: u64,
+    pub packet: Packet,
+}
+
+/// All the packets sent in the network, in order.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Log {
+    entries: Vec<LogEntry>,
+}
+
+impl Log {
+    pub fn entries(&self) -> &[LogEntry] {
+        &self.entries
+    }
+
+    pub fn sent_by(&self, address: Byte) -> impl Iterator<Item = &LogEntry> {
+        self.entries
+            .iter()
+            .filter(move |entry| entry.packet.from == address)
+    }
+
+    pub fn sent_to(&self, address: Byte) -> impl Iterator<Item = &LogEntry> {
+        self.entries
+            .iter()
+            .filter(move |entry| entry.packet.to == address)
+    }
+
+    pub fn first_sent_to(&self, address: Byte) -> Option<&LogEntry> {
+        self.sent_to(address).next()
+    }
+
+    /// The first packet sent by `address` with the same Y as the previous packet it sent.
+    pub fn first_repeated_y(&self, address: Byte) -> Option<&LogEntry> {
+        let mut repeated_y = RepeatedY::new(address);
+        self.entries.iter().find(|entry| repeated_y.check(entry))
+    }
+}
+
+// Finds the packets sent by `address` with the same Y as the previous packet it sent, given
+// the packets in order.
+struct RepeatedY {
+    address: Byte,
+    previous: Option<Byte>,
+}
+
+impl RepeatedY {
+    fn new(address: Byte) -> Self {
+        RepeatedY {
+            address,
+            previous: None,
+        }
+    }
+
+    fn check(&mut self, entry: &LogEntry) -> bool {
+        if entry.packet.from != self.address {
+            return false;
+        }
+        let repeated = self.previous == Some(entry.packet.y);
+        self.previous = Some(entry.packet.y);
+        repeated
+    }
+}
+
+/// The condition for `Network::stop_when` holding for the packet `Log::first_repeated_y` finds.
+pub fn repeated_y(address: Byte) -> impl FnMut(&LogEntry) -> bool {
+    let mut repeated_y = RepeatedY::new(address);
+    move |entry| repeated_y.check(entry)
+}
+
+/// A special address of the network.
+pub trait Device {
+    fn receive(&mut self, packet: Packet);
+
+    /// Called when all the computers are idle: the packet sent to wake them up, if any.
+    fn on_idle(&mut self) -> Option<Packet> {
+        None
+    }
+}
+
+/// Keeps the last packet it received and sends it to the address 0 when the network is idle.
+#[derive(Debug, Default)]
+pub struct Nat {
+    last: Option<Packet>,
+}
+
+impl Device for Nat {
+    fn receive(&mut self, packet: Packet) {
+        self.last = Some(packet);
+    }
+
+    fn on_idle(&mut self) -> Option<Packet> {
+        self.last.take().map(|packet| Packet {
+            from: NAT_ADDRESS,
+            to: 0,
+            ..packet
+        })
+    }
+}
+
+type StopCondition = Box<dyn FnMut(&LogEntry) -> bool>;
+
+pub struct Network {
+    size: usize,
+    buffers: Vec<Vec<Byte>>,
+    devices: HashMap<Byte, Box<dyn Device>>,
+    log: Log,
+    stop: Option<StopCondition>,
+    stopped: bool,
+}
+
+impl Network {
+    /// A network of `size` computers, without devices.
+    pub fn new(size: usize) -> Self {
+        Network {
+            size,
+            buffers: vec![Vec::new(); size],
+            devices: HashMap::new(),
+            log: Log::default(),
+            stop: None,
+            stopped: false,
+        }
+    }
+
+    /// A network of `size` computers with a `Nat` at `NAT_ADDRESS`.
+    pub fn with_nat(size: usize) -> Self {
+        let mut network = Network::new(size);
+        network.add_device(NAT_ADDRESS, Box::new(Nat::default()));
+        network
+    }
+
+    pub fn add_device(&mut self, address: Byte, device: Box<dyn Device>) {
+        assert!(
+            address < 0 || address >= self.size as Byte,
+            "address {} is taken by a computer",
+            address
+        );
+        self.devices.insert(address, device);
+    }
+
+    /// Stops the run once `condition` holds for a packet, checked on every packet as it's sent
+    /// (the condition keeps its own state if it depends on the previous packets).
+    pub fn stop_when<F: FnMut(&LogEntry) -> bool + 'static>(&mut self, condition: F) {
+        self.stop = Some(Box::new(condition));
+    }
+
+    pub fn log(&self) -> &Log {
+        &self.log
+    }
+
+    pub fn into_log(self) -> Log {
+        self.log
+    }
+
+    /// Creates the computers running `code`, ready to be run by the returned scheduler.
+    pub fn boot(self, code: &[Byte]) -> Scheduler<Network> {
+        let size = self.size;
+        let mut scheduler = Scheduler::new(self);
+        for address in 0..size {
+            let node = scheduler.add(Intcode::new(code.to_vec()));
+            scheduler.push_input(node, address as Byte);
+        }
+        scheduler
+    }
+
+    fn send(&mut self, packet: Packet, inputs: &mut Inputs) {
+        let entry = LogEntry {
+            time: inputs.executed(),
+            packet,
+        };
+        self.log.entries.push(entry);
+        if 0 <= packet.to && packet.to < self.size as Byte {
+            inputs.push(packet.to as usize, packet.x);
+            inputs.push(packet.to as usize, packet.y);
+        } else if let Some(device) = self.devices.get_mut(&packet.to) {
+            device.receive(packet);
+        }
+        if let Some(stop) = self.stop.as_mut() {
+            // The packets sent after the stop (like the other devices' on idle) don't clear it.
+            self.stopped |= stop(&entry);
+        }
+    }
+}
+
+impl Topology for Network {
+    fn route(&mut self, from: usize, value: Byte, inputs: &mut Inputs) {
+        let buffer = &mut self.buffers[from];
+        buffer.push(value);
+        if let [to, x, y] = buffer[..] {
+            buffer.clear();
+            let packet = Packet {
+                from: from as Byte,
+                to,
+                x,
+                y,
+            };
+            self.send(packet, inputs);
+        }
+    }
+
+    fn idle_input(&self, _node: usize) -> Option<Byte> {
+        Some(-1)
+    }
+
+    fn on_idle(&mut self, inputs: &mut Inputs) -> bool {
+        let mut addresses: Vec<Byte> = self.devices.keys().copied().collect();
+        addresses.sort_unstable();
+        let mut woken = false;
+        for address in addresses {
+            if let Some(packet) = self.devices.get_mut(&address).unwrap().on_idle() {
+                self.send(packet, inputs);
+                woken = true;
+            }
+        }
+        woken
+    }
+
+    fn stopped(&self) -> bool {
+        self.stopped
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::assemble;
+    use crate::scheduler::Outcome;
+
+    // The program: sends (address + 1, address, 7) once, then forwards every packet it gets
+    // to the address 255 with Y increased by its own address.
+    const FORWARDER: &str = "
+                in [addr]
+                add [addr], #1, [t]
+                out [t]
+                out [addr]
+                out #7
+        loop:   in [x]
+                eq [x], #-1, [t]
+                jt [t], #loop
+                in [y]
+                add [y], [addr], [y]
+                out #255
+                out [x]
+                out [y]
+                jt #1, #loop
+        addr:   db 0
+        t:      db 0
+        x:      db 0
+        y:      db 0
+        ";
+
+    #[test]
+    fn test_packets() {
+        let code = assemble(FORWARDER).unwrap();
+        let mut scheduler = Network::new(3).boot(&code);
+        assert_eq!(Outcome::Idle, scheduler.run().unwrap());
+        let log = scheduler.topology().log();
+        // The packet to the address 3 is dropped.
+        assert_eq!(
+            vec![(0, 1), (1, 2), (1, 255), (2, 3), (2, 255)],
+            log.entries()
+                .iter()
+                .map(|entry| (entry.packet.from, entry.packet.to))
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(
+            Packet {
+                from: 1,
+                to: 255,
+                x: 0,
+                y: 8
+            },
+            log.first_sent_to(255).unwrap().packet
+        );
+        assert!(log
+            .entries()
+            .windows(2)
+            .all(|pair| pair[0].time <= pair[1].time));
+    }
+
+    #[test]
+    fn test_nat() {
+        // The computer 0 sends back whatever the NAT sends it when idle, so the same packet
+        // goes around until the run is stopped.
+        let code = assemble(FORWARDER).unwrap();
+        let mut network = Network::with_nat(2);
+        let mut nat_packets = 0;
+        network.stop_when(move |entry| {
+            if entry.packet.from == NAT_ADDRESS {
+                nat_packets += 1;
+            }
+            nat_packets >= 3
+        });
+        let mut scheduler = network.boot(&code);
+        assert_eq!(Outcome::Stopped, scheduler.run().unwrap());
+        let log = scheduler.topology().log();
+        let nat_ys: Vec<Byte> = log.sent_by(NAT_ADDRESS).map(|e| e.packet.y).collect();
+        assert_eq!(vec![8, 8, 8], nat_ys);
+        assert_eq!(
+            Some(&log.sent_by(NAT_ADDRESS).nth(1).unwrap().packet),
+            log.first_repeated_y(NAT_ADDRESS).map(|entry| &entry.packet)
+        );
+    }
+
+    // Sends the packet once, when the network is idle.
+    struct Once(Option<Packet>);
+
+    impl Device for Once {
+        fn receive(&mut self, _packet: Packet) {}
+
+        fn on_idle(&mut self) -> Option<Packet> {
+            self.0.take()
+        }
+    }
+
+    #[test]
+    fn test_stop_on_idle() {
+        // The packet of the device 100 stops the run, the one of 101 sent after it doesn't
+        // resume it.
+        let mut network = Network::new(1);
+        for from in [100, 101] {
+            let packet = Packet {
+                from,
+                to: 0,
+                x: 1,
+                y: 2,
+            };
+            network.add_device(from, Box::new(Once(Some(packet))));
+        }
+        network.stop_when(|entry| entry.packet.from == 100);
+        // The program: read values forever.
+        let mut scheduler = network.boot(&[3, 5, 1105, 1, 0, 0]);
+        assert_eq!(Outcome::Stopped, scheduler.run().unwrap());
+        assert_eq!(2, scheduler.topology().log().entries().len());
+    }
+
+    #[test]
+    fn test_repeated_y() {
+        let code = assemble(FORWARDER).unwrap();
+        let mut network = Network::with_nat(2);
+        network.stop_when(repeated_y(NAT_ADDRESS));
+        let mut scheduler = network.boot(&code);
+        assert_eq!(Outcome::Stopped, scheduler.run().unwrap());
+        let log = scheduler.topology().log();
+        assert_eq!(log.entries().last(), log.first_repeated_y(NAT_ADDRESS));
+    }
+}
@@ -0,0 +1,207 @@
+//! A deliberately simple Intcode interpreter, the reference for checking the real one.
+//!
+//! It shares no code with `Intcode`: no decoding cache, no memory backends, no tracing, just
+//! the rules of the puzzles written down as directly as possible (plus the error reporting of
+//! `Intcode`, so that failing programs can be compared as well). It is slow and it should stay
+//! that way: see `fuzz` for the differential testing.
+
+use super::{Byte, ErrorKind, State, DEFAULT_MAX_ADDRESS};
+use std::collections::VecDeque;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Reference {
+    /// The memory, growing on writes; the cells past its end are zeros.
+    pub memory: Vec<Byte>,
+    pub ip: usize,
+    pub relative_base: Byte,
+    pub inputs: VecDeque<Byte>,
+    pub max_address: usize,
+    pub checked_arithmetic: bool,
+    /// The number of executed instructions, counted like `Intcode::executed`.
+    pub executed: u64,
+}
+
+impl Reference {
+    pub fn new(code: Vec<Byte>) -> Self {
+        Reference {
+            memory: code,
+            ip: 0,
+            relative_base: 0,
+            inputs: VecDeque::new(),
+            max_address: DEFAULT_MAX_ADDRESS,
+            checked_arithmetic: false,
+            executed: 0,
+        }
+    }
+
+    fn load(&self, address: usize) -> Byte {
+        if address < self.memory.len() {
+            self.memory[address]
+        } else {
+            0
+        }
+    }
+
+    fn store(&mut self, address: usize, value: Byte) {
+        while self.memory.len() <= address {
+            self.memory.push(0);
+        }
+        self.memory[address] = value;
+    }
+
+    fn check_address(&self, operand: usize, address: Byte) -> Result<usize, ErrorKind> {
+        if address < 0 {
+            return Err(ErrorKind::NegativeAddress { operand, address });
+        }
+        if address as u128 > self.max_address as u128 {
+            return Err(ErrorKind::AddressOutOfRange { operand, address });
+        }
+        Ok(address as usize)
+    }
+
+    // The mode digit of the (1-based) operand.
+    fn mode(&self, operand: usize) -> Byte {
+        let mut modes = self.load(self.ip) / 100;
+        for _ in 1..operand {
+            modes /= 10;
+        }
+        modes % 10
+    }
+
+    // The address the (1-based) operand refers to.
+    fn address(&self, operand: usize) -> Result<usize, ErrorKind> {
+        let parameter = self.load(self.ip + operand);
+        match self.mode(operand) {
+            0 => self.check_address(operand, parameter),
+            1 => Ok(self.ip + operand),
+            _ => match parameter.checked_add(self.relative_base) {
+                Some(address) => self.check_address(operand, address),
+                None => Err(ErrorKind::Overflow),
+            },
+        }
+    }
+
+    fn get(&self, operand: usize) -> Result<Byte, ErrorKind> {
+        Ok(self.load(self.address(operand)?))
+    }
+
+    fn set(&mut self, operand: usize, value: Byte) -> Result<(), ErrorKind> {
+        if self.mode(operand) == 1 {
+            return Err(ErrorKind::WriteInImmediateMode { operand });
+        }
+        let address = self.address(operand)?;
+        self.store(address, value);
+        Ok(())
+    }
+
+    fn add(&self, a: Byte, b: Byte) -> Result<Byte, ErrorKind> {
+        if self.checked_arithmetic {
+            a.checked_add(b).ok_or(ErrorKind::Overflow)
+        } else {
+            Ok(a.wrapping_add(b))
+        }
+    }
+
+    fn mul(&self, a: Byte, b: Byte) -> Result<Byte, ErrorKind> {
+        if self.checked_arithmetic {
+            a.checked_mul(b).ok_or(ErrorKind::Overflow)
+        } else {
+            Ok(a.wrapping_mul(b))
+        }
+    }
+
+    /// Executes one instruction, like `Intcode::step`.
+    pub fn step(&mut self) -> Result<Option<State>, ErrorKind> {
+        let opcode = self.load(self.ip) % 100;
+        let arity = match opcode {
+            1 | 2 | 7 | 8 => 3,
+            5 | 6 => 2,
+            3 | 4 | 9 => 1,
+            99 => 0,
+            _ => return Err(ErrorKind::UnknownOpcode(opcode)),
+        };
+        // All the modes are checked before executing anything.
+        for operand in 1..=arity {
+            let mode = self.mode(operand);
+            if mode > 2 {
+                return Err(ErrorKind::InvalidMode { operand, mode });
+            }
+        }
+        match opcode {
+            1 => {
+                let value = self.add(self.get(1)?, self.get(2)?)?;
+                self.set(3, value)?;
+                self.ip += 4;
+            }
+            2 => {
+                let value = self.mul(self.get(1)?, self.get(2)?)?;
+                self.set(3, value)?;
+                self.ip += 4;
+            }
+            3 => {
+                let value = match self.inputs.pop_front() {
+                    Some(value) => value,
+                    None => return Ok(Some(State::NeedsInput)),
+                };
+                self.set(1, value)?;
+                self.ip += 2;
+            }
+            4 => {
+                let value = self.get(1)?;
+                self.ip += 2;
+                self.executed += 1;
+                return Ok(Some(State::Output(value)));
+            }
+            5 | 6 => {
+                let condition = self.get(1)?;
+                let target = self.get(2)?;
+                if (condition != 0) == (opcode == 5) {
+                    self.ip = self.check_address(2, target)?;
+                } else {
+                    self.ip += 3;
+                }
+            }
+            7 => {
+                let value = (self.get(1)? < self.get(2)?) as Byte;
+                self.set(3, value)?;
+                self.ip += 4;
+            }
+            8 => {
+                let value = (self.get(1)? == self.get(2)?) as Byte;
+                self.set(3, value)?;
+                self.ip += 4;
+            }
+            9 => {
+                self.relative_base = self.add(self.relative_base, self.get(1)?)?;
+                self.ip += 2;
+            }
+            _ => return Ok(Some(State::Halted)),
+        }
+        self.executed += 1;
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reference() {
+        // The day 9 quine.
+        let code = vec![
+            109, 1, 204, -1, 1001, 100, 1, 100, 1008, 100, 16, 101, 1006, 101, 0, 99,
+        ];
+        let mut machine = Reference::new(code.to_vec());
+        let mut output = Vec::new();
+        loop {
+            match machine.step() {
+                Ok(Some(State::Output(value))) => output.push(value),
+                Ok(Some(State::Halted)) => break,
+                Ok(_) => {}
+                Err(err) => panic!("{}", err),
+            }
+        }
+        assert_eq!(code, output);
+    }
+}
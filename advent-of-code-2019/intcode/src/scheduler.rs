@@ -0,0 +1,455 @@
+//! Deterministic single-threaded execution of many Intcode machines.
+//!
+//! The `Scheduler` runs the machines round-robin, each one for at most a budget of
+//! instructions per turn, and hands their outputs to a `Topology` that routes them to the
+//! inputs of other machines.
+//!
+//! A machine can't make progress if it halted, if it is blocked on an input (the default) or
+//! if it is idle: the topology may feed a machine with an idle value instead of blocking it
+//! (like `-1` in a network), and the machine is considered idle once it keeps polling for a
+//! whole budget of instructions after reading the idle value, without producing any output or
+//! getting a real input.
+//!
+//! Whether a polling machine will ever send something can't be known in general, so idleness
+//! is an approximation depending on the budget. Before the run takes the machines for idle,
+//! the idle ones are resumed for one more period: the ones that send something weren't idle,
+//! and they get twice as long to poll from then on. A machine polling for longer than that
+//! before sending may still be taken for idle.
+//!
+//! When no machine can make progress, the topology gets a chance to push new inputs
+//! (`Topology::on_idle`), otherwise the run stops. The topology can also stop the run at any
+//! time (`Topology::stopped`).
+
+use super::{Byte, Intcode, RuntimeError, State};
+use std::error;
+use std::fmt;
+
+/// How the outputs of the machines are delivered to the inputs of others.
+pub trait Topology {
+    /// Handles the `value` produced by the machine `from`.
+    fn route(&mut self, from: usize, value: Byte, inputs: &mut Inputs);
+
+    /// The value a machine reads when none is queued: `None` (the default) blocks it.
+    fn idle_input(&self, _node: usize) -> Option<Byte> {
+        None
+    }
+
+    /// Called when no machine can make progress. Returns whether new inputs were pushed.
+    fn on_idle(&mut self, _inputs: &mut Inputs) -> bool {
+        false
+    }
+
+    /// Checked after every output and idle period: returning `true` ends the run.
+    fn stopped(&self) -> bool {
+        false
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Outcome {
+    /// All the machines halted.
+    Halted,
+    /// No machine can make progress, but some of them are blocked on inputs.
+    Deadlock,
+    /// All the machines that didn't halt are idle.
+    Idle,
+    /// The topology stopped the run.
+    Stopped,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct NodeError {
+    pub node: usize,
+    pub error: RuntimeError,
+}
+
+impl fmt::Display for NodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "node {}: {}", self.node, self.error)
+    }
+}
+
+impl error::Error for NodeError {}
+
+struct Node {
+    machine: Intcode,
+    halted: bool,
+    blocked: bool,
+    // The number of instructions executed since the machine read an idle value, `None` if it
+    // produced an output or got a real input since.
+    polling: Option<usize>,
+    // How long the machine polls before it's idle, in budgets.
+    patience: usize,
+    // Whether the idle machine was resumed to confirm that it's idle.
+    confirming: bool,
+}
+
+impl Node {
+    fn is_idle(&self, budget: usize) -> bool {
+        self.polling
+            .is_some_and(|steps| steps >= budget * self.patience)
+    }
+
+    fn can_progress(&self, budget: usize) -> bool {
+        !self.halted && !self.blocked && !self.is_idle(budget)
+    }
+}
+
+/// The access to the input queues of the machines given to the topology.
+pub struct Inputs<'a> {
+    nodes: &'a mut [Node],
+}
+
+impl<'a> Inputs<'a> {
+    pub fn push(&mut self, node: usize, value: Byte) {
+        let node = &mut self.nodes[node];
+        node.machine.push_input(value);
+        node.blocked = false;
+        node.polling = None;
+        node.confirming = false;
+    }
+
+    /// The number of machines.
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    /// The total number of instructions executed by the machines.
+    pub fn executed(&self) -> u64 {
+        self.nodes.iter().map(|node| node.machine.executed()).sum()
+    }
+}
+
+pub const DEFAULT_BUDGET: usize = 1000;
+
+pub struct Scheduler<T: Topology> {
+    nodes: Vec<Node>,
+    topology: T,
+    budget: usize,
+}
+
+impl<T: Topology> Scheduler<T> {
+    pub fn new(topology: T) -> Self {
+        Scheduler {
+            nodes: Vec::new(),
+            topology,
+            budget: DEFAULT_BUDGET,
+        }
+    }
+
+    /// Adds a machine and returns its node index.
+    pub fn add(&mut self, machine: Intcode) -> usize {
+        self.nodes.push(Node {
+            machine,
+            halted: false,
+            blocked: false,
+            polling: None,
+            patience: 1,
+            confirming: false,
+        });
+        self.nodes.len() - 1
+    }
+
+    /// Sets the maximum number of instructions a machine executes in one turn. A machine polling
+    /// for that many instructions is taken for idle (see the module docs).
+    pub fn set_budget(&mut self, budget: usize) {
+        self.budget = budget.max(1);
+    }
+
+    pub fn push_input(&mut self, node: usize, value: Byte) {
+        Inputs {
+            nodes: &mut self.nodes,
+        }
+        .push(node, value);
+    }
+
+    pub fn machine(&self, node: usize) -> &Intcode {
+        &self.nodes[node].machine
+    }
+
+    pub fn is_halted(&self, node: usize) -> bool {
+        self.nodes[node].halted
+    }
+
+    pub fn topology(&self) -> &T {
+        &self.topology
+    }
+
+    pub fn into_topology(self) -> T {
+        self.topology
+    }
+
+    // Runs the machine for one turn. Returns whether it produced any output.
+    fn run_node(&mut self, i: usize) -> Result<bool, NodeError> {
+        let mut sent = false;
+        for _ in 0..self.budget {
+            let node = &mut self.nodes[i];
+            let state = node
+                .machine
+                .step()
+                .map_err(|error| NodeError { node: i, error })?;
+            if let Some(steps) = node.polling.as_mut() {
+                *steps += 1;
+            }
+            match state {
+                None => {}
+                Some(State::Output(value)) => {
+                    if node.confirming {
+                        // It was taken for idle too early.
+                        node.patience *= 2;
+                        node.confirming = false;
+                    }
+                    node.polling = None;
+                    sent = true;
+                    let mut inputs = Inputs {
+                        nodes: &mut self.nodes,
+                    };
+                    self.topology.route(i, value, &mut inputs);
+                    if self.topology.stopped() {
+                        return Ok(sent);
+                    }
+                }
+                Some(State::NeedsInput) => match self.topology.idle_input(i) {
+                    Some(value) => {
+                        node.machine.push_input(value);
+                        node.polling.get_or_insert(0);
+                    }
+                    None => {
+                        node.blocked = true;
+                        return Ok(sent);
+                    }
+                },
+                Some(State::Halted) => {
+                    node.halted = true;
+                    return Ok(sent);
+                }
+            }
+        }
+        Ok(sent)
+    }
+
+    /// Runs the machines until none of them can make progress.
+    pub fn run(&mut self) -> Result<Outcome, NodeError> {
+        // Whether the idle machines were resumed once since the last output.
+        let mut confirmed = false;
+        loop {
+            for i in 0..self.nodes.len() {
+                if self.nodes[i].can_progress(self.budget) {
+                    if self.run_node(i)? {
+                        confirmed = false;
+                    }
+                    if self.topology.stopped() {
+                        return Ok(Outcome::Stopped);
+                    }
+                }
+            }
+            let budget = self.budget;
+            if self.nodes.iter().any(|node| node.can_progress(budget)) {
+                continue;
+            }
+            if self.nodes.iter().all(|node| node.halted) {
+                return Ok(Outcome::Halted);
+            }
+            if !confirmed {
+                confirmed = true;
+                let mut resumed = false;
+                for node in self.nodes.iter_mut().filter(|node| node.is_idle(budget)) {
+                    node.polling = Some(0);
+                    node.confirming = true;
+                    resumed = true;
+                }
+                if resumed {
+                    continue;
+                }
+            }
+            let mut inputs = Inputs {
+                nodes: &mut self.nodes,
+            };
+            let woken = self.topology.on_idle(&mut inputs);
+            if self.topology.stopped() {
+                return Ok(Outcome::Stopped);
+            }
+            confirmed = false;
+            if !woken {
+                let blocked = self.nodes.iter().any(|node| node.blocked);
+                return Ok(if blocked {
+                    Outcome::Deadlock
+                } else {
+                    Outcome::Idle
+                });
+            }
+        }
+    }
+}
+
+/// Connects every machine to the next one, and the last machine to the first one.
+pub struct Ring {
+    last_outputs: Vec<Option<Byte>>,
+}
+
+impl Ring {
+    pub fn new(size: usize) -> Self {
+        Ring {
+            last_outputs: vec![None; size],
+        }
+    }
+
+    pub fn last_output(&self, node: usize) -> Option<Byte> {
+        self.last_outputs[node]
+    }
+}
+
+impl Topology for Ring {
+    fn route(&mut self, from: usize, value: Byte, inputs: &mut Inputs) {
+        self.last_outputs[from] = Some(value);
+        inputs.push((from + 1) % inputs.len(), value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The program: read a value, output it increased by one; repeat until the value is >= 10.
+    fn incrementer() -> Intcode {
+        Intcode::new(vec![
+            3, 16, 1001, 16, 1, 16, 4, 16, 1007, 16, 10, 17, 1005, 17, 0, 99, 0, 0,
+        ])
+    }
+
+    #[test]
+    fn test_ring() {
+        let mut scheduler = Scheduler::new(Ring::new(3));
+        for _ in 0..3 {
+            scheduler.add(incrementer());
+        }
+        scheduler.push_input(0, 0);
+        // The incrementers halt after the value reaches 10.
+        assert_eq!(Ok(Outcome::Halted), scheduler.run());
+        assert_eq!(Some(10), scheduler.topology().last_output(0));
+        assert_eq!(Some(12), scheduler.topology().last_output(2));
+        assert!(scheduler.is_halted(0));
+    }
+
+    #[test]
+    fn test_deadlock() {
+        let mut scheduler = Scheduler::new(Ring::new(2));
+        scheduler.add(incrementer());
+        scheduler.add(incrementer());
+        assert_eq!(Ok(Outcome::Deadlock), scheduler.run());
+    }
+
+    #[test]
+    fn test_halted() {
+        let mut scheduler = Scheduler::new(Ring::new(2));
+        scheduler.add(Intcode::new(vec![104, 1, 99]));
+        scheduler.add(Intcode::new(vec![3, 0, 99]));
+        scheduler.set_budget(1);
+        assert_eq!(Ok(Outcome::Halted), scheduler.run());
+        assert_eq!(1, scheduler.machine(1).memory().as_slice()[0]);
+    }
+
+    // Collects the outputs of the node 1, waking it up with a new value when all nodes are idle.
+    struct Echo {
+        wakeups: usize,
+        received: Vec<Byte>,
+    }
+
+    impl Topology for Echo {
+        fn route(&mut self, from: usize, value: Byte, _inputs: &mut Inputs) {
+            assert_eq!(1, from);
+            self.received.push(value);
+        }
+
+        fn idle_input(&self, _node: usize) -> Option<Byte> {
+            Some(-1)
+        }
+
+        fn on_idle(&mut self, inputs: &mut Inputs) -> bool {
+            self.wakeups += 1;
+            if self.wakeups > 3 {
+                return false;
+            }
+            inputs.push(1, self.wakeups as Byte);
+            true
+        }
+    }
+
+    #[test]
+    fn test_idle() {
+        let mut scheduler = Scheduler::new(Echo {
+            wakeups: 0,
+            received: Vec::new(),
+        });
+        // The programs: read values forever; the second one outputs the ones that are not -1.
+        scheduler.add(Intcode::new(vec![3, 5, 1105, 1, 0, 0]));
+        scheduler.add(Intcode::new(vec![
+            3, 100, 1008, 100, -1, 101, 1005, 101, 0, 4, 100, 1105, 1, 0,
+        ]));
+        assert_eq!(Ok(Outcome::Idle), scheduler.run());
+        assert_eq!(vec![1, 2, 3], scheduler.topology().received);
+    }
+
+    // Collects the outputs, feeding -1 to the machines that poll.
+    struct Poll(Vec<Byte>);
+
+    impl Topology for Poll {
+        fn route(&mut self, _from: usize, value: Byte, _inputs: &mut Inputs) {
+            self.0.push(value);
+        }
+
+        fn idle_input(&self, _node: usize) -> Option<Byte> {
+            Some(-1)
+        }
+    }
+
+    #[test]
+    fn test_polling() {
+        // The program: read 5 values, output 7, then read values forever.
+        let code = vec![
+            3, 20, 1001, 21, 1, 21, 1007, 21, 5, 22, 1005, 22, 0, 104, 7, 3, 20, 1105, 1, 15, 0, 0,
+            0,
+        ];
+        for &budget in [DEFAULT_BUDGET, 30].iter() {
+            let mut scheduler = Scheduler::new(Poll(Vec::new()));
+            scheduler.add(Intcode::new(code.to_vec()));
+            scheduler.set_budget(budget);
+            assert_eq!(Ok(Outcome::Idle), scheduler.run());
+            assert_eq!(vec![7], scheduler.topology().0);
+        }
+    }
+
+    #[test]
+    fn test_long_polling() {
+        // The program: read 10 values, output 7, then read values forever. It polls for two
+        // budgets and more before it sends something.
+        let code = vec![
+            3, 20, 1001, 21, 1, 21, 1007, 21, 10, 22, 1005, 22, 0, 104, 7, 3, 20, 1105, 1, 15, 0,
+            0, 0,
+        ];
+        let mut scheduler = Scheduler::new(Poll(Vec::new()));
+        scheduler.add(Intcode::new(code.to_vec()));
+        scheduler.set_budget(20);
+        assert_eq!(Ok(Outcome::Idle), scheduler.run());
+        assert_eq!(vec![7], scheduler.topology().0);
+        // It's given twice as long from then on.
+        assert_eq!(2, scheduler.nodes[0].patience);
+    }
+
+    #[test]
+    fn test_node_error() {
+        let mut scheduler = Scheduler::new(Ring::new(2));
+        scheduler.add(Intcode::new(vec![99]));
+        scheduler.add(Intcode::new(vec![42]));
+        let err = scheduler.run().unwrap_err();
+        assert_eq!(1, err.node);
+        assert_eq!(
+            "node 1: unknown opcode 42 at ip 0 (instruction 42, relative base 0)",
+            err.to_string()
+        );
+    }
+}
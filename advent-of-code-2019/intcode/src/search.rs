@@ -0,0 +1,230 @@
+//! Parallel brute-force sweeps over the parameters of Intcode programs.
+//!
+//! A `Trial` describes one run of a program: the memory patches applied before starting it
+//! (like the noun and the verb of day 2) and the inputs it is fed with. `sweep` runs a trial
+//! per candidate on all the cores and stops as soon as the result of one of them satisfies
+//! the predicate. The generic `find_first` and `map` do the same for the searches that need
+//! more than a single run per candidate (e.g. a circuit of machines).
+//!
+//! The results don't depend on the number of threads: `find_first` returns the first matching
+//! candidate in the given order, `map` keeps the order of the candidates.
+
+use super::{Byte, ErrorKind, Intcode, RuntimeError, State};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+
+/// One run of a program.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Trial {
+    pub patches: Vec<(usize, Byte)>,
+    /// The inputs, in the order they are read.
+    pub inputs: Vec<Byte>,
+}
+
+/// The result of a trial that halted.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Outcome {
+    pub memory: Vec<Byte>,
+    pub outputs: Vec<Byte>,
+}
+
+impl Trial {
+    pub fn new() -> Self {
+        Trial::default()
+    }
+
+    pub fn patch(mut self, address: usize, value: Byte) -> Self {
+        self.patches.push((address, value));
+        self
+    }
+
+    pub fn inputs(mut self, inputs: &[Byte]) -> Self {
+        self.inputs.extend_from_slice(inputs);
+        self
+    }
+
+    /// Runs the program until it halts. Fails with `ErrorKind::InputExhausted` if it needs
+    /// more inputs than given, and with `ErrorKind::AddressOutOfRange` if a patch is above the
+    /// maximum address.
+    pub fn run(&self, code: &[Byte]) -> Result<Outcome, RuntimeError> {
+        let mut machine = Intcode::new(code.to_vec());
+        for &(address, value) in self.patches.iter() {
+            machine.write_memory(address, value)?;
+        }
+        for &input in self.inputs.iter() {
+            machine.push_input(input);
+        }
+        let mut outputs = Vec::new();
+        loop {
+            match machine.resume()? {
+                State::NeedsInput => return Err(machine.error(ErrorKind::InputExhausted)),
+                State::Output(value) => outputs.push(value),
+                State::Halted => {
+                    return Ok(Outcome {
+                        memory: machine.into_memory().into_vec(),
+                        outputs,
+                    })
+                }
+            }
+        }
+    }
+}
+
+/// The number of threads used by the sweeps.
+pub fn threads() -> usize {
+    thread::available_parallelism().map_or(1, |n| n.get())
+}
+
+/// Returns the first candidate satisfying `predicate`, checking them in parallel.
+///
+/// The candidates are handed out in order, so once a match is found, the candidates after it
+/// are not checked anymore.
+pub fn find_first<C, P>(candidates: &[C], predicate: P) -> Option<&C>
+where
+    C: Sync,
+    P: Fn(&C) -> bool + Sync,
+{
+    let next = AtomicUsize::new(0);
+    let found = AtomicUsize::new(candidates.len());
+    thread::scope(|scope| {
+        for _ in 0..threads().min(candidates.len()) {
+            scope.spawn(|| loop {
+                let index = next.fetch_add(1, Ordering::Relaxed);
+                if index >= found.load(Ordering::Relaxed) {
+                    break;
+                }
+                if predicate(&candidates[index]) {
+                    found.fetch_min(index, Ordering::Relaxed);
+                    break;
+                }
+            });
+        }
+    });
+    candidates.get(found.into_inner())
+}
+
+/// Applies `f` to all the candidates in parallel.
+pub fn map<C, R, F>(candidates: &[C], f: F) -> Vec<R>
+where
+    C: Sync,
+    R: Send,
+    F: Fn(&C) -> R + Sync,
+{
+    let next = AtomicUsize::new(0);
+    let results = Mutex::new(Vec::with_capacity(candidates.len()));
+    thread::scope(|scope| {
+        for _ in 0..threads().min(candidates.len()) {
+            scope.spawn(|| loop {
+                let index = next.fetch_add(1, Ordering::Relaxed);
+                if index >= candidates.len() {
+                    break;
+                }
+                let result = f(&candidates[index]);
+                results.lock().unwrap().push((index, result));
+            });
+        }
+    });
+    let mut results = results.into_inner().unwrap();
+    results.sort_unstable_by_key(|(index, _)| *index);
+    results.into_iter().map(|(_, result)| result).collect()
+}
+
+/// Runs the trial of each candidate and returns the first candidate whose outcome satisfies
+/// `predicate`. The trials that fail don't match.
+pub fn sweep<'a, C, T, P>(
+    code: &[Byte],
+    candidates: &'a [C],
+    trial: T,
+    predicate: P,
+) -> Option<&'a C>
+where
+    C: Sync,
+    T: Fn(&C) -> Trial + Sync,
+    P: Fn(&Outcome) -> bool + Sync,
+{
+    find_first(candidates, |candidate| match trial(candidate).run(code) {
+        Ok(outcome) => predicate(&outcome),
+        Err(_) => false,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicU64;
+
+    #[test]
+    fn test_trial() {
+        // The program: [0] = [13] * [14], then output the input plus [0].
+        let code = vec![2, 13, 14, 0, 3, 15, 1, 15, 0, 15, 4, 15, 99, 0, 0, 0];
+        let outcome = Trial::new()
+            .patch(13, 3)
+            .patch(14, 5)
+            .inputs(&[4])
+            .run(&code)
+            .unwrap();
+        assert_eq!(15, outcome.memory[0]);
+        assert_eq!(vec![19], outcome.outputs);
+        assert_eq!(
+            ErrorKind::InputExhausted,
+            Trial::new().run(&code).unwrap_err().kind
+        );
+        assert!(matches!(
+            Trial::new()
+                .patch(usize::MAX, 1)
+                .run(&code)
+                .unwrap_err()
+                .kind,
+            ErrorKind::AddressOutOfRange { operand: 0, .. }
+        ));
+    }
+
+    #[test]
+    fn test_sweep() {
+        // The day 2 style search: [0] = [1] * [2] + 7.
+        let code = vec![2, 0, 0, 0, 1, 0, 9, 0, 99, 7];
+        let candidates: Vec<(Byte, Byte)> = (0..10)
+            .flat_map(|noun| (0..10).map(move |verb| (noun, verb)))
+            .collect();
+        let found = sweep(
+            &code,
+            &candidates,
+            |&(noun, verb)| {
+                Trial::new()
+                    .patch(1, 10 + noun)
+                    .patch(2, 10 + verb)
+                    .patch(10 + noun as usize, noun)
+                    .patch(10 + verb as usize, verb)
+            },
+            |outcome| outcome.memory[0] == 43,
+        );
+        // 4 * 9 is the first match in the order of the candidates, 6 * 6 would match too.
+        assert_eq!(Some(&(4, 9)), found);
+        assert_eq!(None, sweep(&code, &candidates, |_| Trial::new(), |_| false));
+    }
+
+    #[test]
+    fn test_find_first_stops_early() {
+        let candidates: Vec<usize> = (0..100_000).collect();
+        let checked = AtomicU64::new(0);
+        let found = find_first(&candidates, |&candidate| {
+            checked.fetch_add(1, Ordering::Relaxed);
+            candidate % 1000 == 999
+        });
+        assert_eq!(Some(&999), found);
+        assert!(checked.into_inner() < 10_000);
+        assert_eq!(None, find_first(&[] as &[usize], |_| true));
+    }
+
+    #[test]
+    fn test_map() {
+        let candidates: Vec<Byte> = (0..1000).collect();
+        let squares = map(&candidates, |&n| {
+            Trial::new().inputs(&[n]).run(&[3, 0, 2, 0, 0, 0, 4, 0, 99])
+        });
+        for (n, outcome) in candidates.iter().zip(squares) {
+            assert_eq!(vec![n * n], outcome.unwrap().outputs);
+        }
+    }
+}
@@ -0,0 +1,436 @@
+//! Recording and replaying the IO of Intcode programs.
+//!
+//! A `Recorder` runs a machine with an `Io` and keeps every value that went in or out,
+//! together with the number of instructions executed before the IO instruction. The session
+//! is stored as text, one value per line:
+//!
+//! ```text
+//! intcode-session 1
+//! in 0 5
+//! out 3 7
+//! ```
+//!
+//! Replaying a session feeds the recorded inputs back to a machine and checks that it produces
+//! the same outputs at the same points of the execution.
+
+use super::memory::Memory;
+use super::{Byte, Intcode, Io, RuntimeError, State};
+use std::cell::Cell;
+use std::error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::Path;
+
+const HEADER: &str = "intcode-session 1";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Entry {
+    /// `at` is the number of instructions executed before the IO instruction.
+    Input {
+        at: u64,
+        value: Byte,
+    },
+    Output {
+        at: u64,
+        value: Byte,
+    },
+}
+
+impl fmt::Display for Entry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Entry::Input { at, value } => write!(f, "in {} {}", at, value),
+            Entry::Output { at, value } => write!(f, "out {} {}", at, value),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Session {
+    pub entries: Vec<Entry>,
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn parse_entry(line: &str) -> Option<Entry> {
+    let mut fields = line.split_whitespace();
+    let kind = fields.next()?;
+    let at = fields.next()?.parse().ok()?;
+    let value = fields.next()?.parse().ok()?;
+    if fields.next().is_some() {
+        return None;
+    }
+    match kind {
+        "in" => Some(Entry::Input { at, value }),
+        "out" => Some(Entry::Output { at, value }),
+        _ => None,
+    }
+}
+
+impl Session {
+    pub fn inputs(&self) -> Vec<Byte> {
+        self.entries
+            .iter()
+            .filter_map(|entry| match entry {
+                Entry::Input { value, .. } => Some(*value),
+                _ => None,
+            })
+            .collect()
+    }
+
+    pub fn outputs(&self) -> Vec<Byte> {
+        self.entries
+            .iter()
+            .filter_map(|entry| match entry {
+                Entry::Output { value, .. } => Some(*value),
+                _ => None,
+            })
+            .collect()
+    }
+
+    pub fn save(&self, out: &mut dyn Write) -> io::Result<()> {
+        writeln!(out, "{}", HEADER)?;
+        for entry in self.entries.iter() {
+            writeln!(out, "{}", entry)?;
+        }
+        Ok(())
+    }
+
+    pub fn load(input: &mut dyn Read) -> io::Result<Session> {
+        let mut lines = BufReader::new(input).lines();
+        if lines.next().transpose()?.as_deref() != Some(HEADER) {
+            return Err(invalid_data("not an intcode session".to_string()));
+        }
+        let mut entries = Vec::new();
+        for line in lines {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let entry = parse_entry(&line)
+                .ok_or_else(|| invalid_data(format!("invalid entry: {}", line)))?;
+            entries.push(entry);
+        }
+        Ok(Session { entries })
+    }
+
+    pub fn save_to_file<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let mut file = io::BufWriter::new(fs::File::create(path)?);
+        self.save(&mut file)?;
+        file.flush()
+    }
+
+    pub fn load_from_file<P: AsRef<Path>>(path: P) -> io::Result<Session> {
+        Session::load(&mut fs::File::open(path)?)
+    }
+}
+
+/// Runs machines with the wrapped `Io`, recording the session.
+pub struct Recorder<'a> {
+    io: &'a mut dyn Io,
+    session: Session,
+}
+
+impl<'a> Recorder<'a> {
+    pub fn new(io: &'a mut dyn Io) -> Self {
+        Recorder {
+            io,
+            session: Session::default(),
+        }
+    }
+
+    /// Runs the machine until it halts. Fails with `ErrorKind::InputExhausted` if the `Io` has
+    /// no more input: the session recorded so far is kept.
+    pub fn run<M: Memory>(&mut self, machine: &mut Intcode<M>) -> Result<(), RuntimeError> {
+        // The step before an IO tells its point of the execution to the recording `Io`.
+        let at = Cell::new(0);
+        let mut recording = Recording {
+            io: &mut *self.io,
+            session: &mut self.session,
+            at: &at,
+        };
+        machine.run_with(&mut recording, |machine| {
+            let state = machine.step()?;
+            match state {
+                Some(State::NeedsInput) => at.set(machine.executed()),
+                // The output instruction was already executed.
+                Some(State::Output(_)) => at.set(machine.executed() - 1),
+                _ => {}
+            }
+            Ok(state)
+        })
+    }
+
+    pub fn session(&self) -> &Session {
+        &self.session
+    }
+
+    pub fn into_session(self) -> Session {
+        self.session
+    }
+}
+
+// Records the IO of a `Recorder` run at the point given by `at`.
+struct Recording<'r> {
+    io: &'r mut dyn Io,
+    session: &'r mut Session,
+    at: &'r Cell<u64>,
+}
+
+impl<'r> Io for Recording<'r> {
+    fn input(&mut self) -> Byte {
+        self.try_input().expect("the input is exhausted")
+    }
+
+    fn try_input(&mut self) -> Option<Byte> {
+        let value = self.io.try_input()?;
+        let at = self.at.get();
+        self.session.entries.push(Entry::Input { at, value });
+        Some(value)
+    }
+
+    fn output(&mut self, value: Byte) {
+        let at = self.at.get();
+        self.session.entries.push(Entry::Output { at, value });
+        self.io.output(value);
+    }
+}
+
+/// What the machine did instead of the recorded entry.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Action {
+    Input { at: u64 },
+    Output { at: u64, value: Byte },
+    Halt { at: u64 },
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Action::Input { at } => write!(f, "in {}", at),
+            Action::Output { at, value } => write!(f, "out {} {}", at, value),
+            Action::Halt { at } => write!(f, "halt {}", at),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ReplayError {
+    Runtime(RuntimeError),
+    /// The machine diverged from the session at the entry `index` (`expected` is `None` past
+    /// the end of the session).
+    Mismatch {
+        index: usize,
+        expected: Option<Entry>,
+        found: Action,
+    },
+}
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ReplayError::Runtime(err) => write!(f, "{}", err),
+            ReplayError::Mismatch {
+                index,
+                expected: Some(expected),
+                found,
+            } => write!(
+                f,
+                "entry {}: expected `{}`, found `{}`",
+                index, expected, found
+            ),
+            ReplayError::Mismatch {
+                index,
+                expected: None,
+                found,
+            } => write!(f, "entry {}: unexpected `{}`", index, found),
+        }
+    }
+}
+
+impl error::Error for ReplayError {}
+
+impl From<RuntimeError> for ReplayError {
+    fn from(err: RuntimeError) -> Self {
+        ReplayError::Runtime(err)
+    }
+}
+
+/// Feeds the recorded inputs to the machine, checking its outputs against the session.
+///
+/// The replay succeeds if the machine follows the whole session and then halts or waits
+/// for another input (a recording of an interactive session ends this way).
+pub fn replay<M: Memory>(machine: &mut Intcode<M>, session: &Session) -> Result<(), ReplayError> {
+    let mut entries = session.entries.iter().copied().enumerate();
+    loop {
+        let found = match machine.resume()? {
+            State::NeedsInput => Action::Input {
+                at: machine.executed(),
+            },
+            State::Output(value) => Action::Output {
+                at: machine.executed() - 1,
+                value,
+            },
+            State::Halted => Action::Halt {
+                at: machine.executed(),
+            },
+        };
+        let (index, expected) = match entries.next() {
+            Some((index, entry)) => (index, Some(entry)),
+            None if matches!(found, Action::Output { .. }) => (session.entries.len(), None),
+            None => return Ok(()),
+        };
+        match (expected, found) {
+            (Some(Entry::Input { at, value }), Action::Input { at: found_at })
+                if at == found_at =>
+            {
+                machine.push_input(value)
+            }
+            (
+                Some(Entry::Output { at, value }),
+                Action::Output {
+                    at: found_at,
+                    value: found,
+                },
+            ) if at == found_at && value == found => {}
+            _ => {
+                return Err(ReplayError::Mismatch {
+                    index,
+                    expected,
+                    found,
+                })
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ErrorKind;
+
+    // The program: read values and output their running sum until the sum exceeds 10.
+    fn machine() -> Intcode {
+        Intcode::new(vec![
+            3, 18, 1, 18, 16, 16, 4, 16, 1007, 16, 11, 17, 1005, 17, 0, 99, 0, 0, 0,
+        ])
+    }
+
+    struct Script {
+        inputs: Vec<Byte>,
+        outputs: Vec<Byte>,
+    }
+
+    impl Script {
+        fn new(inputs: &[Byte]) -> Self {
+            Script {
+                inputs: inputs.to_vec(),
+                outputs: Vec::new(),
+            }
+        }
+    }
+
+    impl Io for Script {
+        fn input(&mut self) -> Byte {
+            self.try_input().unwrap()
+        }
+
+        fn output(&mut self, value: Byte) {
+            self.outputs.push(value);
+        }
+
+        fn try_input(&mut self) -> Option<Byte> {
+            if self.inputs.is_empty() {
+                None
+            } else {
+                Some(self.inputs.remove(0))
+            }
+        }
+    }
+
+    #[test]
+    fn test_record_and_replay() {
+        let mut io = Script::new(&[3, 4, 5]);
+        let mut recorder = Recorder::new(&mut io);
+        recorder.run(&mut machine()).unwrap();
+        let session = recorder.into_session();
+        assert_eq!(vec![3, 7, 12], io.outputs);
+        assert_eq!(vec![3, 4, 5], session.inputs());
+        assert_eq!(vec![3, 7, 12], session.outputs());
+        assert_eq!(
+            &[
+                Entry::Input { at: 0, value: 3 },
+                Entry::Output { at: 2, value: 3 }
+            ],
+            &session.entries[..2]
+        );
+
+        let mut data = Vec::new();
+        session.save(&mut data).unwrap();
+        assert!(String::from_utf8(data.to_vec())
+            .unwrap()
+            .starts_with("intcode-session 1\nin 0 3\nout 2 3\nin 5 4\n"));
+        let loaded = Session::load(&mut &data[..]).unwrap();
+        assert_eq!(session, loaded);
+
+        assert_eq!(Ok(()), replay(&mut machine(), &loaded));
+    }
+
+    #[test]
+    fn test_replay_mismatch() {
+        let mut session = Session {
+            entries: vec![
+                Entry::Input { at: 0, value: 3 },
+                Entry::Output { at: 2, value: 4 },
+            ],
+        };
+        assert_eq!(
+            Err(ReplayError::Mismatch {
+                index: 1,
+                expected: Some(Entry::Output { at: 2, value: 4 }),
+                found: Action::Output { at: 2, value: 3 },
+            }),
+            replay(&mut machine(), &session)
+        );
+
+        // A session recorded up to an input replays fine, outputs past its end don't.
+        session.entries.truncate(1);
+        let err = replay(&mut machine(), &session).unwrap_err();
+        assert_eq!("entry 1: unexpected `out 2 3`", err.to_string());
+        session.entries.push(Entry::Output { at: 2, value: 3 });
+        assert_eq!(Ok(()), replay(&mut machine(), &session));
+
+        // The machine halts before the end of the session.
+        let session = Session {
+            entries: vec![Entry::Input { at: 0, value: 20 }],
+        };
+        let mut halting = machine();
+        halting.write_memory(0, 99).unwrap();
+        let err = replay(&mut halting, &session).unwrap_err();
+        assert_eq!(
+            "entry 0: expected `in 0 20`, found `halt 0`",
+            err.to_string()
+        );
+    }
+
+    #[test]
+    fn test_input_exhausted() {
+        let mut io = Script::new(&[1]);
+        let mut recorder = Recorder::new(&mut io);
+        let err = recorder.run(&mut machine()).unwrap_err();
+        assert_eq!(ErrorKind::InputExhausted, err.kind);
+        assert_eq!(vec![1], recorder.session().outputs());
+        assert_eq!(Ok(()), replay(&mut machine(), recorder.session()));
+    }
+
+    #[test]
+    fn test_load_errors() {
+        assert!(Session::load(&mut "in 0 1\n".as_bytes()).is_err());
+        assert!(Session::load(&mut "intcode-session 1\nin 0\n".as_bytes()).is_err());
+        assert!(Session::load(&mut "intcode-session 1\njump 0 1\n".as_bytes()).is_err());
+    }
+}
@@ -0,0 +1,294 @@
+//! Saving and loading the state of Intcode machines.
+//!
+//! A snapshot is stored as text, one field per line:
+//!
+//! ```text
+//! intcode-snapshot 1
+//! ip 10
+//! relative-base 0
+//! executed 4
+//! input 1,2
+//! memory 0 3,9,7,9,10,9,4,9,99,-1,8
+//! memory 1000000000000 42
+//! ```
+//!
+//! The memory is stored as its segments (see `Memory::segments`), one per line with its start
+//! address, so the sparse memories stay small.
+
+use super::Byte;
+use std::fs;
+use std::io;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::Path;
+
+const HEADER: &str = "intcode-snapshot 1";
+
+/// The state of a machine: see `Intcode::snapshot` and `Intcode::restore`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Snapshot {
+    /// The segments of the memory: `(start address, values)` in the address order.
+    pub memory: Vec<(usize, Vec<Byte>)>,
+    pub ip: usize,
+    pub relative_base: Byte,
+    /// The inputs queued, but not consumed yet.
+    pub input: Vec<Byte>,
+    /// The number of instructions executed so far.
+    pub executed: u64,
+}
+
+fn join(values: &[Byte]) -> String {
+    values
+        .iter()
+        .map(|value| value.to_string())
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn parse_values(str: &str) -> io::Result<Vec<Byte>> {
+    if str.trim().is_empty() {
+        return Ok(Vec::new());
+    }
+    str.split(',')
+        .map(|value| {
+            value
+                .trim()
+                .parse()
+                .map_err(|_| invalid_data(format!("invalid value: {}", value)))
+        })
+        .collect()
+}
+
+impl Snapshot {
+    pub fn save(&self, out: &mut dyn Write) -> io::Result<()> {
+        writeln!(out, "{}", HEADER)?;
+        writeln!(out, "ip {}", self.ip)?;
+        writeln!(out, "relative-base {}", self.relative_base)?;
+        writeln!(out, "executed {}", self.executed)?;
+        writeln!(out, "input {}", join(&self.input))?;
+        for (start, values) in self.memory.iter() {
+            writeln!(out, "memory {} {}", start, join(values))?;
+        }
+        Ok(())
+    }
+
+    pub fn load(input: &mut dyn Read) -> io::Result<Snapshot> {
+        let mut lines = BufReader::new(input).lines();
+        if lines.next().transpose()?.as_deref() != Some(HEADER) {
+            return Err(invalid_data("not an intcode snapshot".to_string()));
+        }
+        let mut field = |name: &str| -> io::Result<String> {
+            let line = lines.next().transpose()?.unwrap_or_default();
+            match line.split_once(' ') {
+                Some((key, value)) if key == name => Ok(value.to_string()),
+                _ if line == name => Ok(String::new()),
+                _ => Err(invalid_data(format!("expected `{}`, got: {}", name, line))),
+            }
+        };
+        let ip = field("ip")?;
+        let ip = ip
+            .parse()
+            .map_err(|_| invalid_data(format!("invalid ip: {}", ip)))?;
+        let relative_base = field("relative-base")?;
+        let relative_base = relative_base
+            .parse()
+            .map_err(|_| invalid_data(format!("invalid relative base: {}", relative_base)))?;
+        let executed = field("executed")?;
+        let executed = executed
+            .parse()
+            .map_err(|_| invalid_data(format!("invalid executed count: {}", executed)))?;
+        let input = parse_values(&field("input")?)?;
+        let mut memory = Vec::new();
+        for line in lines {
+            let line = line?;
+            let segment = match line.strip_prefix("memory ") {
+                Some(segment) => segment,
+                None => return Err(invalid_data(format!("expected `memory`, got: {}", line))),
+            };
+            let (start, values) = segment.split_once(' ').unwrap_or((segment, ""));
+            let start = start
+                .parse()
+                .map_err(|_| invalid_data(format!("invalid address: {}", start)))?;
+            memory.push((start, parse_values(values)?));
+        }
+        Ok(Snapshot {
+            memory,
+            ip,
+            relative_base,
+            input,
+            executed,
+        })
+    }
+
+    pub fn save_to_file<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let mut file = io::BufWriter::new(fs::File::create(path)?);
+        self.save(&mut file)?;
+        file.flush()
+    }
+
+    pub fn load_from_file<P: AsRef<Path>>(path: P) -> io::Result<Snapshot> {
+        Snapshot::load(&mut fs::File::open(path)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::{Memory, PagedMemory};
+    use crate::{Intcode, State};
+
+    // The program: ask for two inputs and output their sum.
+    fn machine() -> Intcode {
+        Intcode::new(vec![3, 11, 3, 12, 1, 11, 12, 13, 4, 13, 99, 0, 0, 0])
+    }
+
+    #[test]
+    fn test_snapshot_and_restore() {
+        let mut machine = machine();
+        machine.push_input(40);
+        assert_eq!(Ok(State::NeedsInput), machine.resume());
+        machine.push_input(2);
+        let snapshot = machine.snapshot();
+        assert_eq!(
+            (2, 0, vec![2]),
+            (snapshot.ip, snapshot.relative_base, snapshot.input.to_vec())
+        );
+
+        // The forked machines continue independently.
+        let mut fork = self::machine();
+        fork.restore(snapshot.clone()).unwrap();
+        let mut clone = machine.clone();
+        assert_eq!(Ok(State::Output(42)), machine.resume());
+        assert_eq!(Ok(State::Output(42)), fork.resume());
+        assert_eq!(Ok(State::Output(42)), clone.resume());
+        let mut restored = self::machine();
+        restored.restore(snapshot.clone()).unwrap();
+        assert_eq!(snapshot, restored.snapshot());
+    }
+
+    #[test]
+    fn test_restore_keeps_settings() {
+        // The program: output [0] + 1 forever.
+        let mut machine = Intcode::new(vec![1001, 0, 1, 8, 4, 8, 1105, 1, 0]);
+        machine.set_checked_arithmetic(true);
+        machine.set_max_address(8);
+        let snapshot = machine.snapshot();
+        assert_eq!(Ok(State::Output(1002)), machine.resume());
+        assert_eq!(2, machine.executed());
+
+        machine.restore(snapshot.clone()).unwrap();
+        assert_eq!(0, machine.executed());
+        machine.write_memory(3, 9).unwrap();
+        // The write to 9 is out of range, as before the restore.
+        assert!(matches!(
+            machine.resume().unwrap_err().kind,
+            crate::ErrorKind::AddressOutOfRange { .. }
+        ));
+
+        machine.restore(snapshot).unwrap();
+        machine.write_memory(2, Byte::MAX).unwrap();
+        assert_eq!(
+            crate::ErrorKind::Overflow,
+            machine.resume().unwrap_err().kind
+        );
+    }
+
+    #[test]
+    fn test_restore_out_of_range() {
+        let mut machine = machine();
+        machine.set_max_address(19);
+        let mut snapshot = machine.snapshot();
+        snapshot.memory.push((19, vec![1, 2]));
+        assert_eq!(
+            crate::ErrorKind::AddressOutOfRange {
+                operand: 0,
+                address: 20
+            },
+            machine.restore(snapshot).unwrap_err().kind
+        );
+        // The machine is left as it was.
+        assert_eq!(14, machine.memory().len());
+
+        let mut snapshot = machine.snapshot();
+        snapshot.memory.push((usize::MAX, vec![1, 2]));
+        assert!(machine.restore(snapshot).is_err());
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let mut machine = machine();
+        machine.push_input(-5);
+        assert_eq!(Ok(State::NeedsInput), machine.resume());
+        let snapshot = machine.snapshot();
+
+        let mut data = Vec::new();
+        snapshot.save(&mut data).unwrap();
+        assert_eq!(
+            "intcode-snapshot 1\nip 2\nrelative-base 0\nexecuted 1\ninput \n\
+             memory 0 3,11,3,12,1,11,12,13,4,13,99,-5,0,0\n",
+            String::from_utf8(data.to_vec()).unwrap()
+        );
+        let loaded = Snapshot::load(&mut &data[..]).unwrap();
+        assert_eq!(snapshot, loaded);
+
+        let mut restored = self::machine();
+        restored.restore(loaded).unwrap();
+        restored.push_input(7);
+        assert_eq!(Ok(State::Output(2)), restored.resume());
+    }
+
+    #[test]
+    fn test_load_errors() {
+        let header = "intcode-snapshot 1\nip 0\nrelative-base 0\nexecuted 0\n";
+        let load = |rest: &str| Snapshot::load(&mut format!("{}{}", header, rest).as_bytes());
+        assert!(Snapshot::load(&mut "memory 0 1,2,3\n".as_bytes()).is_err());
+        assert!(Snapshot::load(&mut "intcode-snapshot 1\nip x\n".as_bytes()).is_err());
+        assert!(
+            Snapshot::load(&mut "intcode-snapshot 1\nip 0\nrelative-base 0\n".as_bytes()).is_err()
+        );
+        assert!(load("").is_err());
+        assert!(load("input\nmemory 0 1,a\n").is_err());
+        assert!(load("input 1,,2\nmemory 0 99\n").is_err());
+        assert!(load("input\nmemory x 1\n").is_err());
+        assert!(load("input\nip 1\n").is_err());
+        // The spaces around the values are allowed.
+        let snapshot = load("input 1, 2\nmemory 0 99 , 0\n").unwrap();
+        assert_eq!(
+            (vec![1, 2], vec![(0, vec![99, 0])]),
+            (snapshot.input, snapshot.memory)
+        );
+    }
+
+    #[test]
+    fn test_sparse_memory() {
+        // The program: [10^12] = 42, then halt.
+        let far = 1_000_000_000_000;
+        let mut machine = Intcode::with_memory(PagedMemory::from_vec(vec![1101, 40, 2, far, 99]));
+        machine.set_max_address(usize::MAX);
+        assert_eq!(Ok(State::Halted), machine.resume());
+        // The first page and the cell at 10^12 only.
+        let snapshot = machine.snapshot();
+        let segments: Vec<_> = snapshot
+            .memory
+            .iter()
+            .map(|(start, values)| (*start, values.len()))
+            .collect();
+        assert_eq!(vec![(0, 1024), (far as usize, 1)], segments);
+
+        let mut data = Vec::new();
+        snapshot.save(&mut data).unwrap();
+        assert!(data.len() < 10_000);
+        assert!(String::from_utf8(data.to_vec())
+            .unwrap()
+            .ends_with("\nmemory 1000000000000 42\n"));
+        let mut restored = Intcode::with_memory(PagedMemory::default());
+        restored.set_max_address(usize::MAX);
+        restored
+            .restore(Snapshot::load(&mut &data[..]).unwrap())
+            .unwrap();
+        assert_eq!(machine.memory(), restored.memory());
+    }
+}
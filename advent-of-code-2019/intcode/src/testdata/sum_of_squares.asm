@@ -0,0 +1,17 @@
+; Reads n, outputs the sum of the squares from 1 to n (a loop with function calls).
+        arb #100
+        in rb+1
+        add #0, #0, rb+2
+loop:   jf rb+1, #done
+        add #back, #0, rb+3
+        add rb+1, #0, rb+4
+        jt #1, #square
+back:   add rb+2, rb+4, rb+2
+        add rb+1, #-1, rb+1
+        jt #1, #loop
+done:   out rb+2
+        hlt
+square: arb #3
+        mul rb+1, rb+1, rb+1
+        arb #-3
+        jt #1, rb+3
@@ -0,0 +1,223 @@
+// Generated by `intcode::transpile::transpile`.
+#[allow(clippy::all, dead_code, unreachable_code, unused_variables)]
+pub mod sum_of_squares {
+    use intcode::{Byte, Io};
+
+    const CODE_LEN: usize = 47;
+    const MAX_ADDRESS: Byte = 16777215;
+
+    fn is_code(address: Byte) -> bool {
+        matches!(address, 0..=46)
+    }
+
+    fn relative(rb: Byte, offset: Byte) -> Option<Byte> {
+        rb.checked_add(offset)
+    }
+
+    fn read(mem: &[Byte], address: Option<Byte>) -> Option<Byte> {
+        let address = address?;
+        if !(0..=MAX_ADDRESS).contains(&address) {
+            return None;
+        }
+        Some(mem.get(address as usize).copied().unwrap_or(0))
+    }
+
+    fn writable(mem: &mut Vec<Byte>, address: Option<Byte>) -> Option<usize> {
+        let address = address?;
+        if !(0..=MAX_ADDRESS).contains(&address) || is_code(address) {
+            return None;
+        }
+        let address = address as usize;
+        if address >= mem.len() {
+            mem.resize(address + 1, 0);
+        }
+        Some(address)
+    }
+
+    fn target(address: Byte) -> Option<usize> {
+        if (0..=MAX_ADDRESS).contains(&address) {
+            Some(address as usize)
+        } else {
+            None
+        }
+    }
+
+    /// Runs the program on the memory initialized with its code. Returns the ip and the
+    /// relative base to continue from in the interpreter if the translated code can't
+    /// handle an instruction.
+    pub fn run(mem: &mut Vec<Byte>, io: &mut dyn Io) -> Result<(), (usize, Byte)> {
+        if mem.len() < CODE_LEN {
+            mem.resize(CODE_LEN, 0);
+        }
+        let mut ip: usize = 0;
+        let mut rb: Byte = 0;
+        loop {
+            match ip {
+                0 => {
+                    // arb #100
+                    let a: Byte = 100;
+                    rb = rb.wrapping_add(a);
+                    // in rb+1
+                    ip = 2;
+                    let Some(d) = writable(mem, relative(rb, 1)) else { break };
+                    mem[d] = io.input();
+                    // add #0, #0, rb+2
+                    ip = 4;
+                    let Some(d) = writable(mem, relative(rb, 2)) else { break };
+                    let a: Byte = 0;
+                    let b: Byte = 0;
+                    mem[d] = Byte::wrapping_add(a, b);
+                    ip = 8;
+                }
+                8 => {
+                    // jf rb+1, #33
+                    let Some(a) = read(mem, relative(rb, 1)) else { break };
+                    if a == 0 {
+                        ip = 33;
+                        continue;
+                    }
+                    ip = 11;
+                }
+                11 => {
+                    // add #22, #0, rb+3
+                    let Some(d) = writable(mem, relative(rb, 3)) else { break };
+                    let a: Byte = 22;
+                    let b: Byte = 0;
+                    mem[d] = Byte::wrapping_add(a, b);
+                    // add rb+1, #0, rb+4
+                    ip = 15;
+                    let Some(d) = writable(mem, relative(rb, 4)) else { break };
+                    let Some(a) = read(mem, relative(rb, 1)) else { break };
+                    let b: Byte = 0;
+                    mem[d] = Byte::wrapping_add(a, b);
+                    // jt #1, #36
+                    ip = 36;
+                    continue;
+                }
+                22 => {
+                    // add rb+2, rb+4, rb+2
+                    let Some(d) = writable(mem, relative(rb, 2)) else { break };
+                    let Some(a) = read(mem, relative(rb, 2)) else { break };
+                    let Some(b) = read(mem, relative(rb, 4)) else { break };
+                    mem[d] = Byte::wrapping_add(a, b);
+                    // add rb+1, #-1, rb+1
+                    ip = 26;
+                    let Some(d) = writable(mem, relative(rb, 1)) else { break };
+                    let Some(a) = read(mem, relative(rb, 1)) else { break };
+                    let b: Byte = -1;
+                    mem[d] = Byte::wrapping_add(a, b);
+                    // jt #1, #8
+                    ip = 8;
+                    continue;
+                }
+                33 => {
+                    // out rb+2
+                    let Some(a) = read(mem, relative(rb, 2)) else { break };
+                    io.output(a);
+                    // hlt
+                    return Ok(());
+                }
+                36 => {
+                    // arb #3
+                    let a: Byte = 3;
+                    rb = rb.wrapping_add(a);
+                    // mul rb+1, rb+1, rb+1
+                    ip = 38;
+                    let Some(d) = writable(mem, relative(rb, 1)) else { break };
+                    let Some(a) = read(mem, relative(rb, 1)) else { break };
+                    let Some(b) = read(mem, relative(rb, 1)) else { break };
+                    mem[d] = Byte::wrapping_mul(a, b);
+                    // arb #-3
+                    let a: Byte = -3;
+                    rb = rb.wrapping_add(a);
+                    // jt #1, rb+3
+                    ip = 44;
+                    let Some(t) = read(mem, relative(rb, 3)) else { break };
+                    let Some(t) = target(t) else { break };
+                    ip = t;
+                    continue;
+                }
+                _ => break,
+            }
+        }
+        Err((ip, rb))
+    }
+}
+
+// Generated by `intcode::transpile::transpile`.
+#[allow(clippy::all, dead_code, unreachable_code, unused_variables)]
+pub mod patch {
+    use intcode::{Byte, Io};
+
+    const CODE_LEN: usize = 10;
+    const MAX_ADDRESS: Byte = 16777215;
+
+    fn is_code(address: Byte) -> bool {
+        matches!(address, 0..=8)
+    }
+
+    fn relative(rb: Byte, offset: Byte) -> Option<Byte> {
+        rb.checked_add(offset)
+    }
+
+    fn read(mem: &[Byte], address: Option<Byte>) -> Option<Byte> {
+        let address = address?;
+        if !(0..=MAX_ADDRESS).contains(&address) {
+            return None;
+        }
+        Some(mem.get(address as usize).copied().unwrap_or(0))
+    }
+
+    fn writable(mem: &mut Vec<Byte>, address: Option<Byte>) -> Option<usize> {
+        let address = address?;
+        if !(0..=MAX_ADDRESS).contains(&address) || is_code(address) {
+            return None;
+        }
+        let address = address as usize;
+        if address >= mem.len() {
+            mem.resize(address + 1, 0);
+        }
+        Some(address)
+    }
+
+    fn target(address: Byte) -> Option<usize> {
+        if (0..=MAX_ADDRESS).contains(&address) {
+            Some(address as usize)
+        } else {
+            None
+        }
+    }
+
+    /// Runs the program on the memory initialized with its code. Returns the ip and the
+    /// relative base to continue from in the interpreter if the translated code can't
+    /// handle an instruction.
+    pub fn run(mem: &mut Vec<Byte>, io: &mut dyn Io) -> Result<(), (usize, Byte)> {
+        if mem.len() < CODE_LEN {
+            mem.resize(CODE_LEN, 0);
+        }
+        let mut ip: usize = 0;
+        let mut rb: Byte = 0;
+        loop {
+            match ip {
+                0 => {
+                    // arb #6
+                    let a: Byte = 6;
+                    rb = rb.wrapping_add(a);
+                    // add #104, #0, rb+0
+                    ip = 2;
+                    let Some(d) = writable(mem, relative(rb, 0)) else { break };
+                    let a: Byte = 104;
+                    let b: Byte = 0;
+                    mem[d] = Byte::wrapping_add(a, b);
+                    // out [9]
+                    let a = mem[9];
+                    io.output(a);
+                    // hlt
+                    return Ok(());
+                }
+                _ => break,
+            }
+        }
+        Err((ip, rb))
+    }
+}
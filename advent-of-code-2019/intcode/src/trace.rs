@@ -0,0 +1,387 @@
+//! Execution tracing and instruction-level profiling.
+//!
+//! A `Tracer` receives an `Event` for every instruction executed by `Intcode::run_traced`
+//! (or `Intcode::step_traced`, the machines with extensions can't be traced). `Profiler`
+//! aggregates the events into a text report, `TraceWriter` and `TraceReader` store and load
+//! them as a compact binary trace.
+
+use super::{Byte, Opcode};
+use std::collections::HashMap;
+use std::io;
+use std::io::{Read, Write};
+
+/// An executed instruction.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Event {
+    pub ip: usize,
+    pub opcode: Opcode,
+    // An instruction reads at most two operands.
+    operands: [Byte; 2],
+    operand_count: usize,
+    /// The written address and value, if any.
+    pub written: Option<(usize, Byte)>,
+}
+
+impl Event {
+    pub fn new(ip: usize, opcode: Opcode) -> Self {
+        Event {
+            ip,
+            opcode,
+            operands: [0; 2],
+            operand_count: 0,
+            written: None,
+        }
+    }
+
+    /// The resolved values of the operands read by the instruction.
+    pub fn operands(&self) -> &[Byte] {
+        &self.operands[..self.operand_count]
+    }
+
+    pub fn push_operand(&mut self, value: Byte) {
+        self.operands[self.operand_count] = value;
+        self.operand_count += 1;
+    }
+}
+
+pub trait Tracer {
+    fn trace(&mut self, event: &Event);
+}
+
+/// Collects all the events in memory.
+impl Tracer for Vec<Event> {
+    fn trace(&mut self, event: &Event) {
+        self.push(event.clone());
+    }
+}
+
+#[derive(Default)]
+pub struct Profiler {
+    pub total: u64,
+    pub hits: HashMap<usize, u64>,
+    pub opcodes: HashMap<Opcode, u64>,
+}
+
+impl Tracer for Profiler {
+    fn trace(&mut self, event: &Event) {
+        self.total += 1;
+        *self.hits.entry(event.ip).or_insert(0) += 1;
+        *self.opcodes.entry(event.opcode).or_insert(0) += 1;
+    }
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// The hit counts of the most executed `count` addresses (the hottest first).
+    pub fn hottest(&self, count: usize) -> Vec<(usize, u64)> {
+        let mut hits: Vec<(usize, u64)> = self.hits.iter().map(|(ip, n)| (*ip, *n)).collect();
+        hits.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        hits.truncate(count);
+        hits
+    }
+
+    /// Renders the opcode histogram and the `top` hottest addresses.
+    pub fn report(&self, top: usize) -> String {
+        let mut report = format!("Total instructions: {}\n\nOpcodes:\n", self.total);
+        let mut opcodes: Vec<(Opcode, u64)> =
+            self.opcodes.iter().map(|(op, n)| (*op, *n)).collect();
+        opcodes.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.code().cmp(&b.0.code())));
+        for (opcode, count) in opcodes {
+            report += &format!(
+                "{:>5} {:>12} {:>6.2}%\n",
+                opcode.mnemonic(),
+                count,
+                percent(count, self.total)
+            );
+        }
+        report += "\nHottest addresses:\n";
+        for (ip, count) in self.hottest(top) {
+            report += &format!(
+                "{:>5} {:>12} {:>6.2}%\n",
+                ip,
+                count,
+                percent(count, self.total)
+            );
+        }
+        report
+    }
+}
+
+fn percent(count: u64, total: u64) -> f64 {
+    100.0 * count as f64 / total.max(1) as f64
+}
+
+const MAGIC: &[u8; 8] = b"ICTRACE1";
+
+// The unsigned counterpart of `Byte` for the variable-length encoding.
+#[cfg(not(feature = "wide"))]
+type Unsigned = u64;
+#[cfg(feature = "wide")]
+type Unsigned = u128;
+
+fn write_varint(out: &mut dyn Write, mut value: Unsigned) -> io::Result<()> {
+    let mut buf = [0u8; 20];
+    let mut len = 0;
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            buf[len] = byte;
+            len += 1;
+            break;
+        }
+        buf[len] = byte | 0x80;
+        len += 1;
+    }
+    out.write_all(&buf[..len])
+}
+
+// Zigzag encoding keeps small negative values short.
+fn write_signed(out: &mut dyn Write, value: Byte) -> io::Result<()> {
+    write_varint(
+        out,
+        ((value << 1) ^ (value >> (Byte::BITS - 1))) as Unsigned,
+    )
+}
+
+// Returns `None` at the end of the input.
+fn read_varint(input: &mut dyn Read) -> io::Result<Option<Unsigned>> {
+    let mut value: Unsigned = 0;
+    let mut shift = 0;
+    loop {
+        let mut byte = [0u8];
+        if input.read(&mut byte)? == 0 {
+            return if shift == 0 {
+                Ok(None)
+            } else {
+                Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "truncated trace",
+                ))
+            };
+        }
+        value |= ((byte[0] & 0x7f) as Unsigned) << shift;
+        if byte[0] & 0x80 == 0 {
+            return Ok(Some(value));
+        }
+        shift += 7;
+        if shift >= Unsigned::BITS {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "invalid varint"));
+        }
+    }
+}
+
+fn read_unsigned(input: &mut dyn Read) -> io::Result<Unsigned> {
+    read_varint(input)?
+        .ok_or_else(|| io::Error::new(io::ErrorKind::UnexpectedEof, "truncated trace"))
+}
+
+fn read_signed(input: &mut dyn Read) -> io::Result<Byte> {
+    let value = read_unsigned(input)?;
+    Ok((value >> 1) as Byte ^ -((value & 1) as Byte))
+}
+
+// The number of operands an executed instruction reads.
+fn read_count(opcode: Opcode) -> usize {
+    match opcode.output_operand() {
+        Some(_) => opcode.arity() - 1,
+        None => opcode.arity(),
+    }
+}
+
+/// Writes the events as a binary trace: for every event the opcode, the ip, the operands and
+/// the written address and value, as variable-length integers.
+pub struct TraceWriter<W: Write> {
+    out: W,
+    error: Option<io::Error>,
+}
+
+impl<W: Write> TraceWriter<W> {
+    pub fn new(mut out: W) -> io::Result<Self> {
+        out.write_all(MAGIC)?;
+        Ok(TraceWriter { out, error: None })
+    }
+
+    fn write_event(&mut self, event: &Event) -> io::Result<()> {
+        write_varint(&mut self.out, event.opcode.code() as Unsigned)?;
+        write_varint(&mut self.out, event.ip as Unsigned)?;
+        for value in event.operands() {
+            write_signed(&mut self.out, *value)?;
+        }
+        if let Some((address, value)) = event.written {
+            write_varint(&mut self.out, address as Unsigned)?;
+            write_signed(&mut self.out, value)?;
+        }
+        Ok(())
+    }
+
+    /// Flushes the trace, reporting the first error that happened while tracing.
+    pub fn finish(mut self) -> io::Result<W> {
+        if let Some(err) = self.error.take() {
+            return Err(err);
+        }
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+impl<W: Write> Tracer for TraceWriter<W> {
+    fn trace(&mut self, event: &Event) {
+        if self.error.is_none() {
+            self.error = self.write_event(event).err();
+        }
+    }
+}
+
+pub struct TraceReader<R: Read> {
+    input: R,
+}
+
+impl<R: Read> TraceReader<R> {
+    pub fn new(mut input: R) -> io::Result<Self> {
+        let mut magic = [0u8; 8];
+        input.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "not a trace"));
+        }
+        Ok(TraceReader { input })
+    }
+
+    fn read_event(&mut self) -> io::Result<Option<Event>> {
+        let code = match read_varint(&mut self.input)? {
+            Some(code) => code,
+            None => return Ok(None),
+        };
+        let opcode = Opcode::from_code(code as Byte)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "invalid opcode"))?;
+        let ip = read_unsigned(&mut self.input)?;
+        let mut event = Event::new(ip as usize, opcode);
+        for _ in 0..read_count(opcode) {
+            event.push_operand(read_signed(&mut self.input)?);
+        }
+        if opcode.output_operand().is_some() {
+            let address = read_unsigned(&mut self.input)?;
+            let value = read_signed(&mut self.input)?;
+            event.written = Some((address as usize, value));
+        }
+        Ok(Some(event))
+    }
+}
+
+impl<R: Read> Iterator for TraceReader<R> {
+    type Item = io::Result<Event>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_event().transpose()
+    }
+}
+
+/// Returns the index of the first event that differs between the two runs (or the length of
+/// the shorter run, if it is a prefix of the other one), `None` if the runs are the same.
+pub fn first_divergence<A, B>(a: A, b: B) -> Option<usize>
+where
+    A: IntoIterator<Item = Event>,
+    B: IntoIterator<Item = Event>,
+{
+    let mut a = a.into_iter();
+    let mut b = b.into_iter();
+    let mut i = 0;
+    loop {
+        match (a.next(), b.next()) {
+            (None, None) => return None,
+            (Some(x), Some(y)) if x == y => i += 1,
+            _ => return Some(i),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Intcode, Io};
+
+    struct Output(Vec<Byte>);
+
+    impl Io for Output {
+        fn input(&mut self) -> Byte {
+            panic!("unexpected input request")
+        }
+
+        fn output(&mut self, value: Byte) {
+            self.0.push(value);
+        }
+    }
+
+    // The program: ask for input, if input is < 8, output 1; else output 0
+    fn run(input: Byte) -> Vec<Event> {
+        let mut machine = Intcode::new(vec![3, 9, 7, 9, 10, 9, 4, 9, 99, -1, 8]);
+        machine.push_input(input);
+        let mut events = Vec::new();
+        while machine.step_traced(&mut events) != Ok(Some(crate::State::Halted)) {}
+        events
+    }
+
+    #[test]
+    fn test_events() {
+        let events = run(7);
+        assert_eq!(4, events.len());
+        assert_eq!((0, Opcode::Input), (events[0].ip, events[0].opcode));
+        assert_eq!(Some((9, 7)), events[0].written);
+        assert_eq!(&[7, 8], events[1].operands());
+        assert_eq!(Some((9, 1)), events[1].written);
+        assert_eq!(&[1], events[2].operands());
+        assert_eq!(None, events[2].written);
+        assert_eq!(Opcode::Stop, events[3].opcode);
+    }
+
+    #[test]
+    fn test_profiler() {
+        let code = vec![
+            109, 1, 204, -1, 1001, 100, 1, 100, 1008, 100, 16, 101, 1006, 101, 0, 99,
+        ];
+        let mut profiler = Profiler::new();
+        let output = crate::run_code_with_inputs(code.to_vec(), Vec::new());
+        let mut io = Output(Vec::new());
+        Intcode::new(code)
+            .run_traced(&mut io, &mut profiler)
+            .unwrap();
+        assert_eq!(output, io.0);
+        // 16 iterations of 5 instructions and the final `hlt`.
+        assert_eq!(16 * 5 + 1, profiler.total);
+        assert_eq!(Some(&16), profiler.opcodes.get(&Opcode::Output));
+        assert_eq!(vec![(0, 16), (2, 16)], profiler.hottest(2));
+        let report = profiler.report(1);
+        assert!(report.starts_with("Total instructions: 81\n"));
+        assert!(report.ends_with("Hottest addresses:\n    0           16  19.75%\n"));
+    }
+
+    #[test]
+    fn test_binary_trace() {
+        let events = run(42);
+        let mut writer = TraceWriter::new(Vec::new()).unwrap();
+        events.iter().for_each(|event| writer.trace(event));
+        let bytes = writer.finish().unwrap();
+        // The header and 4 events of a few bytes each.
+        assert!(bytes.len() < 8 + 4 * 8);
+        let reader = TraceReader::new(&bytes[..]).unwrap();
+        let loaded: Vec<Event> = reader.map(|event| event.unwrap()).collect();
+        assert_eq!(events, loaded);
+    }
+
+    #[test]
+    fn test_first_divergence() {
+        assert_eq!(None, first_divergence(run(42), run(42)));
+        assert_eq!(Some(0), first_divergence(run(7), run(8)));
+        assert_eq!(
+            Some(4),
+            first_divergence(run(7), run(7).into_iter().take(4).chain(run(7)))
+        );
+        assert_eq!(
+            Some(2),
+            first_divergence(run(1), run(1).into_iter().take(2).chain(run(9)))
+        );
+    }
+}
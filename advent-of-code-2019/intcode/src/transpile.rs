@@ -0,0 +1,403 @@
+//! Ahead-of-time translation of Intcode programs to Rust.
+//!
+//! The generated module has a `run` function with a loop over a `match` on the ip: every
+//! basic block of the control-flow graph (see `cfg`) becomes one arm, with the instructions
+//! decoded once at translation time and the memory accessed directly.
+//!
+//! The translation is only valid as long as the code isn't modified: the programs that write
+//! into their code at statically known addresses are rejected, and the other writes are
+//! checked at run time. The generated `run` gives up at the first instruction it can't handle
+//! (a write into the code, a jump outside of the known blocks, an invalid address) and returns
+//! the state to continue with: `run_compiled` resumes the interpreter from there.
+//!
+//! The generated module expects the `intcode` crate to be available as a dependency.
+
+use super::cfg::{analyze, Block, CodeWrite};
+use super::disasm::{Instruction, Param};
+use super::memory::DenseMemory;
+use super::snapshot::Snapshot;
+use super::{Byte, Intcode, Io, Opcode, RuntimeError, DEFAULT_MAX_ADDRESS};
+use std::error;
+use std::fmt;
+use std::fmt::Write;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TranspileError {
+    /// The program writes into its code.
+    SelfModifying(Vec<CodeWrite>),
+}
+
+impl fmt::Display for TranspileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TranspileError::SelfModifying(writes) => {
+                let writes: Vec<_> = writes
+                    .iter()
+                    .map(|write| format!("{} writes [{}]", write.at, write.target))
+                    .collect();
+                write!(f, "self-modifying code: {}", writes.join(", "))
+            }
+        }
+    }
+}
+
+impl error::Error for TranspileError {}
+
+/// The `run` function of a translated program: returns the ip and the relative base to
+/// continue from if it gives up.
+pub type Compiled = fn(&mut Vec<Byte>, &mut dyn Io) -> Result<(), (usize, Byte)>;
+
+/// Runs the translated program on the code like `Intcode::run`, finishing the execution in
+/// the interpreter if the translated code gives up.
+pub fn run_compiled(
+    compiled: Compiled,
+    code: Vec<Byte>,
+    io: &mut dyn Io,
+) -> Result<Vec<Byte>, RuntimeError> {
+    let mut memory = code;
+    match compiled(&mut memory, io) {
+        Ok(()) => Ok(memory),
+        Err((ip, relative_base)) => {
+            let mut machine = Intcode::new(Vec::new());
+            machine.restore(Snapshot {
+                memory: vec![(0, memory)],
+                ip,
+                relative_base,
+                input: Vec::new(),
+                executed: 0,
+            })?;
+            machine.run(io).map(DenseMemory::into_vec)
+        }
+    }
+}
+
+const HELPERS: &str = "
+    fn relative(rb: Byte, offset: Byte) -> Option<Byte> {
+        rb.checked_add(offset)
+    }
+
+    fn read(mem: &[Byte], address: Option<Byte>) -> Option<Byte> {
+        let address = address?;
+        if !(0..=MAX_ADDRESS).contains(&address) {
+            return None;
+        }
+        Some(mem.get(address as usize).copied().unwrap_or(0))
+    }
+
+    fn writable(mem: &mut Vec<Byte>, address: Option<Byte>) -> Option<usize> {
+        let address = address?;
+        if !(0..=MAX_ADDRESS).contains(&address) || is_code(address) {
+            return None;
+        }
+        let address = address as usize;
+        if address >= mem.len() {
+            mem.resize(address + 1, 0);
+        }
+        Some(address)
+    }
+
+    fn target(address: Byte) -> Option<usize> {
+        if (0..=MAX_ADDRESS).contains(&address) {
+            Some(address as usize)
+        } else {
+            None
+        }
+    }
+";
+
+// The code of a memory cell access: statically known addresses within the code are accessed
+// directly, the others are checked.
+struct Emitter {
+    code_len: usize,
+}
+
+impl Emitter {
+    fn direct(&self, address: Byte) -> Option<usize> {
+        if address >= 0 && (address as usize) < self.code_len {
+            Some(address as usize)
+        } else {
+            None
+        }
+    }
+
+    // Emits a `let` binding the value of the operand to `name`.
+    fn read(&self, out: &mut String, name: &str, param: Param) {
+        let _ = match param {
+            Param::Immediate(value) => writeln!(out, "let {}: Byte = {};", name, value),
+            Param::Position(address) => match self.direct(address) {
+                Some(address) => writeln!(out, "let {} = mem[{}];", name, address),
+                None => writeln!(
+                    out,
+                    "let Some({}) = read(mem, Some({})) else {{ break }};",
+                    name, address
+                ),
+            },
+            Param::Relative(offset) => writeln!(
+                out,
+                "let Some({}) = read(mem, relative(rb, {})) else {{ break }};",
+                name, offset
+            ),
+        };
+    }
+
+    // Emits a `let` binding the index of the written cell to `d`.
+    fn destination(&self, out: &mut String, param: Param) {
+        let _ = match param {
+            Param::Position(address) => match self.direct(address) {
+                Some(address) => writeln!(out, "let d = {};", address),
+                None => writeln!(
+                    out,
+                    "let Some(d) = writable(mem, Some({})) else {{ break }};",
+                    address
+                ),
+            },
+            Param::Relative(offset) => writeln!(
+                out,
+                "let Some(d) = writable(mem, relative(rb, {})) else {{ break }};",
+                offset
+            ),
+            Param::Immediate(_) => unreachable!("decoded writes are never immediate"),
+        };
+    }
+
+    // Returns the code of the instruction and whether it always leaves the block.
+    fn instruction(&self, instruction: &Instruction) -> (String, bool) {
+        let mut code = String::new();
+        let params = &instruction.params;
+        match instruction.opcode {
+            Opcode::Add | Opcode::Multiply | Opcode::LessThan | Opcode::Equals => {
+                self.destination(&mut code, params[2]);
+                self.read(&mut code, "a", params[0]);
+                self.read(&mut code, "b", params[1]);
+                let value = match instruction.opcode {
+                    Opcode::Add => "Byte::wrapping_add(a, b)",
+                    Opcode::Multiply => "Byte::wrapping_mul(a, b)",
+                    Opcode::LessThan => "(a < b) as Byte",
+                    _ => "(a == b) as Byte",
+                };
+                let _ = writeln!(code, "mem[d] = {};", value);
+            }
+            Opcode::Input => {
+                self.destination(&mut code, params[0]);
+                code += "mem[d] = io.input();\n";
+            }
+            Opcode::Output => {
+                self.read(&mut code, "a", params[0]);
+                code += "io.output(a);\n";
+            }
+            Opcode::JumpIfTrue | Opcode::JumpIfFalse => {
+                let mut jump = String::new();
+                match params[1] {
+                    Param::Immediate(value) if value < 0 => jump += "break;\n",
+                    Param::Immediate(value) => {
+                        let _ = writeln!(jump, "ip = {};\ncontinue;", value);
+                    }
+                    param => {
+                        self.read(&mut jump, "t", param);
+                        jump += "let Some(t) = target(t) else { break };\nip = t;\ncontinue;\n";
+                    }
+                }
+                let if_true = instruction.opcode == Opcode::JumpIfTrue;
+                match params[0] {
+                    Param::Immediate(value) if (value != 0) == if_true => return (jump, true),
+                    Param::Immediate(_) => {}
+                    param => {
+                        self.read(&mut code, "a", param);
+                        let _ = writeln!(code, "if a {} 0 {{", if if_true { "!=" } else { "==" });
+                        for line in jump.lines() {
+                            let _ = writeln!(code, "    {}", line);
+                        }
+                        code += "}\n";
+                    }
+                }
+            }
+            Opcode::UpdateRelativeBase => {
+                self.read(&mut code, "a", params[0]);
+                code += "rb = rb.wrapping_add(a);\n";
+            }
+            Opcode::Stop => return ("return Ok(());\n".to_string(), true),
+        }
+        (code, false)
+    }
+
+    fn block(&self, out: &mut String, block: &Block) {
+        let indent = " ".repeat(16);
+        let mut body = String::new();
+        let mut leaves = false;
+        for (i, (address, instruction)) in block.instructions.iter().enumerate() {
+            let (code, always_leaves) = self.instruction(instruction);
+            let _ = writeln!(body, "// {}", instruction);
+            // The ip is only needed to give up at this instruction.
+            if i > 0 && code.contains("break") {
+                let _ = writeln!(body, "ip = {};", address);
+            }
+            body += &code;
+            leaves = always_leaves;
+        }
+        if !leaves {
+            let _ = writeln!(body, "ip = {};", block.end);
+        }
+        let _ = writeln!(out, "{}{} => {{", indent, block.start);
+        for line in body.lines() {
+            let _ = writeln!(out, "{}    {}", indent, line);
+        }
+        let _ = writeln!(out, "{}}}", indent);
+    }
+}
+
+/// Translates the program to the Rust module `name`.
+pub fn transpile(code: &[Byte], name: &str) -> Result<String, TranspileError> {
+    let cfg = analyze(code);
+    if !cfg.code_writes.is_empty() {
+        return Err(TranspileError::SelfModifying(cfg.code_writes));
+    }
+    let mut code_ranges: Vec<(usize, usize)> = Vec::new();
+    for block in cfg.blocks.values() {
+        match code_ranges.last_mut() {
+            Some(range) if range.1 >= block.start => range.1 = range.1.max(block.end),
+            _ => code_ranges.push((block.start, block.end)),
+        }
+    }
+    let is_code = if code_ranges.is_empty() {
+        "false".to_string()
+    } else {
+        let ranges: Vec<_> = code_ranges
+            .iter()
+            .map(|(start, end)| format!("{}..={}", start, end - 1))
+            .collect();
+        format!("matches!(address, {})", ranges.join(" | "))
+    };
+
+    let mut out = String::new();
+    out += "// Generated by `intcode::transpile::transpile`.\n";
+    out += "#[allow(clippy::all, dead_code, unreachable_code, unused_variables)]\n";
+    let _ = writeln!(out, "pub mod {} {{", name);
+    out += "    use intcode::{Byte, Io};\n\n";
+    let _ = writeln!(out, "    const CODE_LEN: usize = {};", code.len());
+    let _ = writeln!(
+        out,
+        "    const MAX_ADDRESS: Byte = {};\n",
+        DEFAULT_MAX_ADDRESS
+    );
+    out += "    fn is_code(address: Byte) -> bool {\n";
+    let _ = writeln!(out, "        {}", is_code);
+    out += "    }\n";
+    out += HELPERS;
+    out += "
+    /// Runs the program on the memory initialized with its code. Returns the ip and the
+    /// relative base to continue from in the interpreter if the translated code can't
+    /// handle an instruction.
+    pub fn run(mem: &mut Vec<Byte>, io: &mut dyn Io) -> Result<(), (usize, Byte)> {
+        if mem.len() < CODE_LEN {
+            mem.resize(CODE_LEN, 0);
+        }
+        let mut ip: usize = 0;
+        let mut rb: Byte = 0;
+        loop {
+            match ip {
+";
+    let emitter = Emitter {
+        code_len: code.len(),
+    };
+    for block in cfg.blocks.values() {
+        emitter.block(&mut out, block);
+    }
+    out += "                _ => break,
+            }
+        }
+        Err((ip, rb))
+    }
+}
+";
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::assemble;
+
+    include!("testdata/transpiled.rs");
+
+    // The program: read n, output the sum of the squares from 1 to n (with a function call).
+    const SUM_OF_SQUARES: &str = include_str!("testdata/sum_of_squares.asm");
+
+    // The program: patch its output instruction through the relative base, then output.
+    const PATCH: &str = "
+                    arb #patch
+                    add #104, #0, rb+0
+            patch:  out [x]
+                    hlt
+            x:      db 7
+            ";
+
+    struct Script {
+        inputs: Vec<Byte>,
+        outputs: Vec<Byte>,
+    }
+
+    impl Io for Script {
+        fn input(&mut self) -> Byte {
+            self.inputs.remove(0)
+        }
+
+        fn output(&mut self, value: Byte) {
+            self.outputs.push(value);
+        }
+    }
+
+    fn script(inputs: &[Byte]) -> Script {
+        Script {
+            inputs: inputs.to_vec(),
+            outputs: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_transpile() {
+        // The generated code is checked in to be compiled and run by the tests below: it has to
+        // be regenerated when the translation changes.
+        let code = assemble(SUM_OF_SQUARES).unwrap();
+        let sum_of_squares = transpile(&code, "sum_of_squares").unwrap();
+        let patch = transpile(&assemble(PATCH).unwrap(), "patch").unwrap();
+        assert_eq!(
+            include_str!("testdata/transpiled.rs"),
+            sum_of_squares + "\n" + &patch
+        );
+    }
+
+    #[test]
+    fn test_run_compiled() {
+        let code = assemble(SUM_OF_SQUARES).unwrap();
+        let mut io = script(&[10]);
+        let mut memory = code.to_vec();
+        assert_eq!(Ok(()), sum_of_squares::run(&mut memory, &mut io));
+        assert_eq!(vec![385], io.outputs);
+
+        let mut expected = script(&[10]);
+        let expected_memory = crate::run_code(code.to_vec(), &mut expected).unwrap();
+        assert_eq!(expected_memory, memory);
+    }
+
+    #[test]
+    fn test_fallback() {
+        // The write into the code is detected at run time and the interpreter takes over.
+        let code = assemble(PATCH).unwrap();
+        let mut memory = code.to_vec();
+        assert_eq!(Err((2, 6)), patch::run(&mut memory, &mut script(&[])));
+
+        let mut io = script(&[]);
+        let memory = run_compiled(patch::run, code, &mut io).unwrap();
+        assert_eq!(vec![9], io.outputs);
+        assert_eq!(104, memory[6]);
+    }
+
+    #[test]
+    fn test_self_modifying() {
+        let code = assemble("add #104, #0, [patch]\npatch: out [0]\nhlt").unwrap();
+        assert_eq!(
+            "self-modifying code: 0 writes [4]",
+            transpile(&code, "patch").unwrap_err().to_string()
+        );
+    }
+}